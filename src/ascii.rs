@@ -1,46 +1,231 @@
-use crate::message::AsciiFrame;
-use ffmpeg_next::util::frame::Video;
-
-/// ASCII character palette ordered from light to dark density
-/// 68 characters provide good granularity for luminance mapping
-const PALETTE: &[u8] = b" .'`^\",:;Il!i><~+_-?][}{1)(|\\tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$";
+use crate::dither::{self, Dither};
+use crate::edge;
+use crate::exposure::linear_luminance;
+use crate::glyphs::{self, MASK_HEIGHT, MASK_WIDTH};
+use crate::image::RgbImage;
+use crate::message::{AsciiFrame, CellFormat, PixelFormat, PixelFrame};
+use crate::palette::{Palette, STANDARD as PALETTE};
 
 /// Calculate luminance (brightness) from RGB using Rec. 601 standard
 /// Matches human eye sensitivity: green (0.587) > red (0.299) > blue (0.114)
-fn luminance(r: u8, g: u8, b: u8) -> u8 {
+pub fn luminance(r: u8, g: u8, b: u8) -> u8 {
     let y = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
     y.min(255.0) as u8
 }
 
-/// Select ASCII character based on RGB values
+/// Select ASCII character from the standard palette based on RGB values
 pub fn ascii_for(r: u8, g: u8, b: u8) -> char {
     let y = luminance(r, g, b) as usize;
     let idx = (y * (PALETTE.len() - 1)) / 255;
     PALETTE[idx.min(PALETTE.len() - 1)] as char
 }
 
-/// Convert RGB24 video frame to ASCII art frame
-pub fn to_ascii_frame(rgb: &Video, width: u16, height: u16, mono: bool) -> AsciiFrame {
+/// How the brightness that picks glyphs is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Luma {
+    /// Linear-light luminance, as auto-exposure measures it; saturated colors
+    /// get the glyphs of grays as bright as they look
+    #[default]
+    Linear,
+    /// Rec. 601 weights on gamma-encoded values, darker for saturated colors
+    Encoded,
+}
+
+impl Luma {
+    /// Brightness of a pixel, 0 to 255
+    pub fn of(self, r: u8, g: u8, b: u8) -> u8 {
+        match self {
+            Self::Linear => linear_luminance(r, g, b),
+            Self::Encoded => luminance(r, g, b),
+        }
+    }
+}
+
+impl std::str::FromStr for Luma {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Self::Linear),
+            "encoded" | "rec601" | "gamma" => Ok(Self::Encoded),
+            other => Err(anyhow::anyhow!("Unknown luminance: {}", other)),
+        }
+    }
+}
+
+/// How captured pixels are turned into cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// One pixel per cell, glyph picked by luminance
+    #[default]
+    Ascii,
+    /// Two pixels per cell drawn as '▀' with foreground and background colors
+    HalfBlock,
+    /// 2x4 pixels per cell drawn as braille dots
+    Braille,
+    /// Like `Ascii`, but strong outlines are drawn with directional glyphs
+    Edges,
+    /// 3x5 pixels per cell matched against the shape of each glyph
+    Shapes,
+}
+
+impl RenderMode {
+    /// Pixel resolution to scale the camera to for a frame of `width` x `height` cells
+    pub fn pixel_size(self, width: u16, height: u16) -> (u32, u32) {
+        match self {
+            Self::Ascii | Self::Edges => (width as u32, height as u32),
+            Self::HalfBlock => (width as u32, height as u32 * 2),
+            Self::Braille => (width as u32 * 2, height as u32 * 4),
+            Self::Shapes => (width as u32 * MASK_WIDTH as u32, height as u32 * MASK_HEIGHT as u32),
+        }
+    }
+}
+
+impl std::str::FromStr for RenderMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" => Ok(Self::Ascii),
+            "halfblock" | "half-block" | "blocks" => Ok(Self::HalfBlock),
+            "braille" => Ok(Self::Braille),
+            "edges" | "edge" | "sobel" => Ok(Self::Edges),
+            "shapes" | "shape" => Ok(Self::Shapes),
+            other => Err(anyhow::anyhow!("Unknown render mode: {}", other)),
+        }
+    }
+}
+
+/// Settings for turning pixels into cells
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertOptions {
+    pub mode: RenderMode,
+    /// Glyphs for the luminance based modes (ascii, edges)
+    pub palette: Palette,
+    /// Monochrome mode
+    pub mono: bool,
+    /// Luminance above which a braille dot is raised
+    pub threshold: u8,
+    /// Dithering applied when picking glyphs (ascii) or raising dots (braille)
+    pub dither: Dither,
+    /// Gradient magnitude above which a pixel is drawn as an edge (a black to white step is 255)
+    pub edge_threshold: u8,
+    /// Brightness measure picking glyphs, dots and edges
+    pub luma: Luma,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            mode: RenderMode::Ascii,
+            palette: Palette::standard(),
+            mono: false,
+            threshold: 128,
+            dither: Dither::None,
+            edge_threshold: 48,
+            luma: Luma::default(),
+        }
+    }
+}
+
+/// Renderer a viewer applies to other users' pixel frames, in place of the
+/// cells picked by the sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalRender {
+    Ascii,
+    HalfBlock,
+    Braille,
+    /// Grayscale ASCII glyphs, needing only luma pixels
+    Mono,
+}
+
+impl std::str::FromStr for LocalRender {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" | "classic" => Ok(Self::Ascii),
+            "halfblock" | "half-block" | "blocks" => Ok(Self::HalfBlock),
+            "braille" => Ok(Self::Braille),
+            "mono" => Ok(Self::Mono),
+            other => Err(anyhow::anyhow!("Unknown local renderer: {}", other)),
+        }
+    }
+}
+
+impl LocalRender {
+    /// Pixel layout the renderer needs from senders
+    pub fn pixel_format(self) -> PixelFormat {
+        match self {
+            Self::Mono => PixelFormat::Luma,
+            _ => PixelFormat::Rgb,
+        }
+    }
+
+    /// Conversion settings drawing with `palette`
+    pub fn options(self, palette: Palette) -> ConvertOptions {
+        let mode = match self {
+            Self::Ascii | Self::Mono => RenderMode::Ascii,
+            Self::HalfBlock => RenderMode::HalfBlock,
+            Self::Braille => RenderMode::Braille,
+        };
+        ConvertOptions {
+            mode,
+            palette,
+            mono: self == Self::Mono,
+            // Braille loses all shading without dithering
+            dither: if mode == RenderMode::Braille { Dither::FloydSteinberg } else { Dither::None },
+            ..ConvertOptions::default()
+        }
+    }
+}
+
+/// Render a received pixel frame as `width` x `height` cells
+pub fn render_pixels(pixels: &PixelFrame, width: u16, height: u16, options: &ConvertOptions) -> AsciiFrame {
+    let (pixel_width, pixel_height) = options.mode.pixel_size(width, height);
+    let image = RgbImage::from_pixel_frame(pixels).resize(pixel_width as usize, pixel_height as usize);
+    convert(&image, width, height, options)
+}
+
+/// Convert an RGB image to a frame of `width` x `height` cells
+///
+/// The image must have the resolution given by `RenderMode::pixel_size`.
+pub fn convert(rgb: &RgbImage, width: u16, height: u16, options: &ConvertOptions) -> AsciiFrame {
+    match options.mode {
+        RenderMode::Ascii => to_ascii_frame(rgb, width, height, options),
+        RenderMode::HalfBlock => to_halfblock_frame(rgb, width, height, options.mono),
+        RenderMode::Braille => to_braille_frame(rgb, width, height, options),
+        RenderMode::Edges => to_edge_frame(rgb, width, height, options),
+        RenderMode::Shapes => to_shape_frame(rgb, width, height, options),
+    }
+}
+
+/// Luminance of every pixel, row by row
+fn luma_plane(rgb: &RgbImage, luma: Luma) -> Vec<f32> {
+    rgb.data
+        .chunks_exact(3)
+        .map(|p| luma.of(p[0], p[1], p[2]) as f32)
+        .collect()
+}
+
+/// Convert RGB image to ASCII art frame, one pixel per cell
+pub fn to_ascii_frame(rgb: &RgbImage, width: u16, height: u16, options: &ConvertOptions) -> AsciiFrame {
     let mut frame = AsciiFrame::new(width, height);
-    let stride = rgb.stride(0) as usize;
-    let data = rgb.data(0);
+    let palette = &options.palette;
+    let levels = match options.dither {
+        Dither::None => None,
+        dither => Some(dither::quantize(&luma_plane(rgb, options.luma), rgb.width, palette.levels(), dither)),
+    };
 
     for y in 0..height as usize {
-        let row_offset = y * stride;
         for x in 0..width as usize {
-            // Each RGB24 pixel is 3 bytes: R, G, B
-            let pixel_offset = x * 3;
-            let idx = row_offset + pixel_offset;
-
-            let (r, g, b) = if idx + 2 < data.len() {
-                (data[idx], data[idx + 1], data[idx + 2])
-            } else {
-                (0, 0, 0)
+            let (r, g, b) = rgb.pixel(x, y);
+            let ch = match &levels {
+                Some(levels) if x < rgb.width && y < rgb.height => palette.glyph(levels[y * rgb.width + x]),
+                _ => palette.glyph_for(options.luma.of(r, g, b)),
             };
 
-            let ch = ascii_for(r, g, b);
-
-            if mono {
+            if options.mono {
                 let gray = luminance(r, g, b);
                 frame.set_cell(x as u16, y as u16, ch, gray, gray, gray);
             } else {
@@ -52,24 +237,185 @@ pub fn to_ascii_frame(rgb: &Video, width: u16, height: u16, mono: bool) -> Ascii
     frame
 }
 
-/// Simple brightness/contrast adjustment for better visibility
-pub fn adjust_contrast(frame: &mut AsciiFrame, contrast: f32, brightness: i32) {
-    for i in (0..frame.data.len()).step_by(4) {
-        if i + 3 < frame.data.len() {
-            let r = (frame.data[i + 1] as i32 + brightness).max(0).min(255) as u8;
-            let g = (frame.data[i + 2] as i32 + brightness).max(0).min(255) as u8;
-            let b = (frame.data[i + 3] as i32 + brightness).max(0).min(255) as u8;
+/// Convert RGB image to ASCII art frame with outlines, one pixel per cell
+///
+/// Pixels whose Sobel gradient is stronger than `edge_threshold` get a glyph
+/// following the edge (`/ \ | - _`); flat areas keep the luminance palette.
+pub fn to_edge_frame(rgb: &RgbImage, width: u16, height: u16, options: &ConvertOptions) -> AsciiFrame {
+    let mut frame = to_ascii_frame(rgb, width, height, options);
+    let gradients = edge::sobel(&luma_plane(rgb, options.luma), rgb.width, rgb.height);
+    let threshold = options.edge_threshold as f32;
+
+    for y in 0..(height as usize).min(rgb.height) {
+        for x in 0..(width as usize).min(rgb.width) {
+            let (gx, gy) = gradients[y * rgb.width + x];
+            if gx.hypot(gy) <= threshold {
+                continue;
+            }
+            if let Some((_, r, g, b)) = frame.get_cell(x as u16, y as u16) {
+                frame.set_cell(x as u16, y as u16, edge::edge_glyph(gx, gy), r, g, b);
+            }
+        }
+    }
+
+    frame
+}
+
+/// Convert RGB image to ASCII art frame by glyph shape, 3x5 pixels per cell
+///
+/// Each cell's pixel block is compared with the coverage mask of every glyph
+/// and the closest one wins, so outlines follow the image instead of only the
+/// cell's average brightness. The cell color is the average under the glyph's ink.
+pub fn to_shape_frame(rgb: &RgbImage, width: u16, height: u16, options: &ConvertOptions) -> AsciiFrame {
+    let mut frame = AsciiFrame::new(width, height);
+    let mut block = [0.0; MASK_WIDTH * MASK_HEIGHT];
+    let mut pixels = [(0, 0, 0); MASK_WIDTH * MASK_HEIGHT];
+
+    for cy in 0..height as usize {
+        for cx in 0..width as usize {
+            for (i, (value, pixel)) in block.iter_mut().zip(pixels.iter_mut()).enumerate() {
+                let (r, g, b) = rgb.pixel(cx * MASK_WIDTH + i % MASK_WIDTH, cy * MASK_HEIGHT + i / MASK_WIDTH);
+                *value = options.luma.of(r, g, b) as f32 / 255.0;
+                *pixel = (r, g, b);
+            }
 
-            let r = ((r as f32 - 128.0) * contrast + 128.0).max(0.0).min(255.0) as u8;
-            let g = ((g as f32 - 128.0) * contrast + 128.0).max(0.0).min(255.0) as u8;
-            let b = ((b as f32 - 128.0) * contrast + 128.0).max(0.0).min(255.0) as u8;
+            let glyph = glyphs::best_match(&block);
 
-            // Recalculate character based on new RGB
-            let ch = ascii_for(r, g, b);
-            frame.data[i] = ch as u8;
-            frame.data[i + 1] = r;
-            frame.data[i + 2] = g;
-            frame.data[i + 3] = b;
+            // Average the inked pixels, or the whole block for a blank glyph
+            let mut sum = [0u32; 3];
+            let mut count = 0u32;
+            for (&(r, g, b), &ink) in pixels.iter().zip(glyph.coverage.iter()) {
+                if ink > 0.0 || glyph.density == 0.0 {
+                    sum[0] += r as u32;
+                    sum[1] += g as u32;
+                    sum[2] += b as u32;
+                    count += 1;
+                }
+            }
+            let (r, g, b) = ((sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8);
+
+            if options.mono {
+                let gray = luminance(r, g, b);
+                frame.set_cell(cx as u16, cy as u16, glyph.ch, gray, gray, gray);
+            } else {
+                frame.set_cell(cx as u16, cy as u16, glyph.ch, r, g, b);
+            }
+        }
+    }
+
+    frame
+}
+
+/// Upper half block: the foreground paints the top pixel, the background the bottom one
+const UPPER_HALF: char = '\u{2580}';
+
+/// Convert RGB image to half-block frame, two vertically stacked pixels per cell
+pub fn to_halfblock_frame(rgb: &RgbImage, width: u16, height: u16, mono: bool) -> AsciiFrame {
+    let format = CellFormat {
+        unicode: true,
+        background: true,
+    };
+    let mut frame = AsciiFrame::with_format(width, height, format);
+
+    let shade = |(r, g, b): (u8, u8, u8)| {
+        if mono {
+            let gray = luminance(r, g, b);
+            (gray, gray, gray)
+        } else {
+            (r, g, b)
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let (tr, tg, tb) = shade(rgb.pixel(x as usize, y as usize * 2));
+            let (br, bg, bb) = shade(rgb.pixel(x as usize, y as usize * 2 + 1));
+            frame.set_cell(x, y, UPPER_HALF, tr, tg, tb);
+            frame.set_background(x, y, br, bg, bb);
+        }
+    }
+
+    frame
+}
+
+/// Braille dot bits for each (x, y) position in a 2x4 cell
+const BRAILLE_DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Convert RGB image to braille frame, 2x4 pixels per cell
+///
+/// Bright pixels raise dots; the cell color is the average of its raised pixels.
+pub fn to_braille_frame(rgb: &RgbImage, width: u16, height: u16, options: &ConvertOptions) -> AsciiFrame {
+    let format = CellFormat {
+        unicode: true,
+        ..CellFormat::default()
+    };
+    let mut frame = AsciiFrame::with_format(width, height, format);
+    let dots = raise_dots(rgb, options.threshold, options.dither, options.luma);
+
+    for cy in 0..height as usize {
+        for cx in 0..width as usize {
+            let mut bits = 0u8;
+            let mut sum = [0u32; 3];
+            let mut lit = 0u32;
+
+            for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                for (dx, bit) in row.iter().enumerate() {
+                    let (x, y) = (cx * 2 + dx, cy * 4 + dy);
+                    if x < rgb.width && y < rgb.height && dots[y * rgb.width + x] == 1 {
+                        let (r, g, b) = rgb.pixel(x, y);
+                        bits |= bit;
+                        sum[0] += r as u32;
+                        sum[1] += g as u32;
+                        sum[2] += b as u32;
+                        lit += 1;
+                    }
+                }
+            }
+
+            let ch = char::from_u32(0x2800 + bits as u32).unwrap_or(' ');
+            let avg = |c: u32| c.checked_div(lit).unwrap_or(0) as u8;
+            let (r, g, b) = (avg(sum[0]), avg(sum[1]), avg(sum[2]));
+
+            if options.mono {
+                frame.set_cell(cx as u16, cy as u16, ch, 255, 255, 255);
+            } else {
+                frame.set_cell(cx as u16, cy as u16, ch, r, g, b);
+            }
+        }
+    }
+
+    frame
+}
+
+/// Decide for every pixel whether its dot is raised (1) or not (0)
+///
+/// Shifting luminance so that `threshold` lands on the midpoint between the two
+/// levels lets the dithering treat braille as a two-glyph palette.
+fn raise_dots(rgb: &RgbImage, threshold: u8, dither: Dither, luma: Luma) -> Vec<usize> {
+    let offset = 127.0 - threshold as f32;
+    let luma: Vec<f32> = luma_plane(rgb, luma).into_iter().map(|v| v + offset).collect();
+    dither::quantize(&luma, rgb.width, 2, dither)
+}
+
+/// Brightness/contrast adjustment of finished cells for better visibility
+///
+/// Foreground and background colors are adjusted; glyphs stay as converted,
+/// since re-picking them from colors would lose the palette and the patterns
+/// of the other render modes.
+pub fn adjust_contrast(frame: &mut AsciiFrame, contrast: f32, brightness: i32) {
+    let adjust = |c: u8| {
+        let c = (c as i32 + brightness).clamp(0, 255) as f32;
+        ((c - 128.0) * contrast + 128.0).round().clamp(0.0, 255.0) as u8
+    };
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            if let Some((ch, r, g, b)) = frame.get_cell(x, y) {
+                frame.set_cell(x, y, ch, adjust(r), adjust(g), adjust(b));
+            }
+            if let Some((r, g, b)) = frame.get_background(x, y) {
+                frame.set_background(x, y, adjust(r), adjust(g), adjust(b));
+            }
         }
     }
 }
@@ -99,4 +445,192 @@ mod tests {
         let ch = ascii_for(255, 255, 255);
         assert_eq!(ch, '@');
     }
+
+    #[test]
+    fn test_linear_luma_picks_glyphs() {
+        let mut rgb = RgbImage::new(1, 1);
+        rgb.set_pixel(0, 0, (0, 0, 255));
+        let glyph = |luma| {
+            let options = ConvertOptions {
+                luma,
+                ..ConvertOptions::default()
+            };
+            convert(&rgb, 1, 1, &options).get_cell(0, 0).unwrap().0
+        };
+
+        // Saturated blue looks brighter than Rec. 601 on encoded values says
+        let palette = Palette::standard();
+        assert_eq!(glyph(Luma::Linear), palette.glyph_for(linear_luminance(0, 0, 255)));
+        assert_eq!(glyph(Luma::Encoded), palette.glyph_for(luminance(0, 0, 255)));
+        assert_ne!(glyph(Luma::Linear), glyph(Luma::Encoded));
+        // Grays are the same either way
+        assert_eq!(Luma::Linear.of(128, 128, 128), Luma::Encoded.of(128, 128, 128));
+    }
+
+    #[test]
+    fn test_edge_frame() {
+        // Bright square on a dark background
+        let mut rgb = RgbImage::new(8, 8);
+        for y in 2..6 {
+            for x in 2..6 {
+                rgb.set_pixel(x, y, (200, 200, 200));
+            }
+        }
+
+        let options = ConvertOptions {
+            mode: RenderMode::Edges,
+            ..ConvertOptions::default()
+        };
+        let frame = convert(&rgb, 8, 8, &options);
+        let glyph = |x, y| frame.get_cell(x, y).map(|c| c.0);
+
+        assert_eq!(glyph(1, 3), Some('|'));
+        assert_eq!(glyph(6, 4), Some('|'));
+        assert_eq!(glyph(3, 1), Some('-'));
+        assert_eq!(glyph(4, 6), Some('_'));
+        // Flat areas keep the palette
+        assert_eq!(glyph(0, 0), Some(' '));
+        assert_eq!(glyph(3, 3), ascii_for(200, 200, 200).into());
+        // Edge glyphs keep the pixel color
+        assert_eq!(frame.get_cell(2, 4).map(|c| c.1), Some(200));
+    }
+
+    #[test]
+    fn test_shape_frame() {
+        // A white diagonal from bottom left to top right inside one cell, and a
+        // white bottom row in the next
+        let mut rgb = RgbImage::new(6, 5);
+        for (x, y) in [(2, 0), (2, 1), (1, 2), (0, 3), (0, 4)] {
+            rgb.set_pixel(x, y, (250, 250, 250));
+        }
+        for x in 3..6 {
+            rgb.set_pixel(x, 4, (200, 255, 200));
+        }
+
+        let options = ConvertOptions {
+            mode: RenderMode::Shapes,
+            ..ConvertOptions::default()
+        };
+        assert_eq!(options.mode.pixel_size(2, 1), (6, 5));
+
+        let frame = convert(&rgb, 2, 1, &options);
+        assert_eq!(frame.get_cell(0, 0), Some(('/', 250, 250, 250)));
+        // The color comes from the inked pixels only, not the dark background
+        assert_eq!(frame.get_cell(1, 0), Some(('_', 200, 255, 200)));
+    }
+
+    #[test]
+    fn test_halfblock_frame() {
+        let mut rgb = RgbImage::new(2, 4);
+        rgb.set_pixel(0, 0, (255, 0, 0));
+        rgb.set_pixel(0, 1, (0, 0, 255));
+        rgb.set_pixel(1, 3, (0, 255, 0));
+
+        let frame = to_halfblock_frame(&rgb, 2, 2, false);
+        assert_eq!(frame.get_cell(0, 0), Some((UPPER_HALF, 255, 0, 0)));
+        assert_eq!(frame.get_background(0, 0), Some((0, 0, 255)));
+        assert_eq!(frame.get_cell(1, 1), Some((UPPER_HALF, 0, 0, 0)));
+        assert_eq!(frame.get_background(1, 1), Some((0, 255, 0)));
+    }
+
+    #[test]
+    fn test_render_pixels_locally() {
+        // Top half white, bottom half red
+        let mut rgb = RgbImage::new(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                rgb.set_pixel(x, y, if y < 2 { (255, 255, 255) } else { (255, 0, 0) });
+            }
+        }
+
+        let render = |local: LocalRender, width, height| {
+            let pixels = rgb.to_pixel_frame(local.pixel_format());
+            render_pixels(&pixels, width, height, &local.options(Palette::standard()))
+        };
+
+        let frame = render(LocalRender::HalfBlock, 2, 1);
+        assert_eq!(frame.get_cell(1, 0), Some((UPPER_HALF, 255, 255, 255)));
+        assert_eq!(frame.get_background(1, 0), Some((255, 0, 0)));
+
+        // Mono is sent as luma and drawn in gray
+        let frame = render(LocalRender::Mono, 2, 2);
+        let densest = Palette::standard().glyph_for(255);
+        assert_eq!(frame.get_cell(0, 0), Some((densest, 255, 255, 255)));
+        let (ch, r, g, b) = frame.get_cell(0, 1).unwrap();
+        assert!(ch != densest && r == g && g == b && r < 128);
+
+        assert!("sixel".parse::<LocalRender>().is_err());
+    }
+
+    #[test]
+    fn test_braille_frame_resolution() {
+        // Left cell white, right cell black
+        let mut rgb = RgbImage::new(4, 4);
+        for y in 0..4 {
+            for x in 0..2 {
+                rgb.set_pixel(x, y, (255, 255, 255));
+            }
+        }
+
+        let options = ConvertOptions {
+            mode: RenderMode::Braille,
+            ..ConvertOptions::default()
+        };
+        let frame = convert(&rgb, 2, 1, &options);
+        assert_eq!(frame.get_cell(0, 0), Some(('\u{28ff}', 255, 255, 255)));
+        assert_eq!(frame.get_cell(1, 0), Some(('\u{2800}', 0, 0, 0)));
+
+        // A single raised dot in the bottom right corner
+        let mut rgb = RgbImage::new(2, 4);
+        rgb.set_pixel(1, 3, (200, 200, 200));
+        let frame = convert(&rgb, 1, 1, &options);
+        assert_eq!(frame.get_cell(0, 0).map(|c| c.0), Some('\u{2880}'));
+    }
+
+    #[test]
+    fn test_braille_dither_keeps_mid_tones() {
+        let mut rgb = RgbImage::new(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                rgb.set_pixel(x, y, (100, 100, 100));
+            }
+        }
+
+        // A hard threshold loses the gray entirely, dithering keeps ~40% of dots
+        assert!(raise_dots(&rgb, 128, Dither::None, Luma::Linear).iter().all(|&d| d == 0));
+        let lit = raise_dots(&rgb, 128, Dither::FloydSteinberg, Luma::Linear).iter().filter(|&&d| d == 1).count();
+        let ratio = lit as f32 / (32.0 * 32.0);
+        assert!((ratio - 100.0 / 255.0).abs() < 0.05, "ratio was {}", ratio);
+    }
+
+    #[test]
+    fn test_dithered_ascii_frame() {
+        // Mid gray between two palette glyphs alternates between them
+        let mut rgb = RgbImage::new(16, 4);
+        for y in 0..4 {
+            for x in 0..16 {
+                rgb.set_pixel(x, y, (97, 97, 97));
+            }
+        }
+
+        let plain = to_ascii_frame(&rgb, 16, 4, &ConvertOptions::default());
+        let glyphs = |frame: &AsciiFrame| {
+            let mut seen: Vec<char> = (0..64).filter_map(|i| frame.get_cell(i % 16, i / 16)).map(|c| c.0).collect();
+            seen.sort();
+            seen.dedup();
+            seen
+        };
+        assert_eq!(glyphs(&plain).len(), 1);
+
+        for dither in [Dither::FloydSteinberg, Dither::Bayer] {
+            let options = ConvertOptions {
+                dither,
+                ..ConvertOptions::default()
+            };
+            let frame = to_ascii_frame(&rgb, 16, 4, &options);
+            assert_eq!(glyphs(&frame).len(), 2, "{:?}", dither);
+            // Colors are untouched, only glyphs are dithered
+            assert_eq!(frame.get_cell(3, 2).map(|c| (c.1, c.2, c.3)), Some((97, 97, 97)));
+        }
+    }
 }
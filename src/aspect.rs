@@ -0,0 +1,112 @@
+use crate::graphics;
+
+/// Height of a terminal cell divided by its width, for most fonts
+pub const DEFAULT_CELL_ASPECT: f32 = 2.0;
+
+/// How the camera picture is fitted into a frame of another shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// Fill the frame, cutting off the edges that do not fit
+    #[default]
+    Crop,
+    /// Show the whole picture, with black bars around it
+    Letterbox,
+    /// Fill the frame by distorting the picture
+    Stretch,
+}
+
+impl std::str::FromStr for Fit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "crop" | "fill" => Ok(Self::Crop),
+            "letterbox" | "fit" => Ok(Self::Letterbox),
+            "stretch" => Ok(Self::Stretch),
+            other => Err(anyhow::anyhow!("Unknown fit: {}", other)),
+        }
+    }
+}
+
+/// Parse a cell aspect given on the command line
+pub fn parse_cell_aspect(value: &str) -> anyhow::Result<f32> {
+    let aspect: f32 = value.parse()?;
+    if !aspect.is_finite() || aspect <= 0.0 {
+        anyhow::bail!("Cell aspect must be a positive number, not {}", value);
+    }
+    Ok(aspect)
+}
+
+/// Cell aspect of the terminal we run in, from its reported pixel size
+///
+/// Viewers' terminals are not known to the sender, so ours stands in for them.
+pub fn detect_cell_aspect() -> f32 {
+    let (width, height) = graphics::cell_size();
+    if width == 0 || height == 0 {
+        return DEFAULT_CELL_ASPECT;
+    }
+    height as f32 / width as f32
+}
+
+/// Size to scale a `source` picture to, in pixels of a `target` pixel grid
+/// covering `cells` terminal cells of the given aspect
+///
+/// With `Crop` the result covers the grid and is cut down to it, with
+/// `Letterbox` it fits inside the grid and is padded out to it.
+pub fn content_size(source: (u32, u32), target: (u32, u32), cells: (u16, u16), cell_aspect: f32, fit: Fit) -> (u32, u32) {
+    let (source_w, source_h) = source;
+    let (target_w, target_h) = target;
+    let degenerate = source_w == 0 || source_h == 0 || cells.0 == 0 || cells.1 == 0;
+    if fit == Fit::Stretch || degenerate || !cell_aspect.is_finite() || cell_aspect <= 0.0 {
+        return target;
+    }
+
+    // Shape of a grid pixel on screen, in cell widths
+    let pixel_w = cells.0 as f32 / target_w as f32;
+    let pixel_h = cells.1 as f32 * cell_aspect / target_h as f32;
+
+    // Picture size on screen that keeps its aspect while covering or fitting the frame
+    let (frame_w, frame_h) = (cells.0 as f32, cells.1 as f32 * cell_aspect);
+    let aspect = source_w as f32 / source_h as f32;
+    let width = match fit {
+        Fit::Crop => frame_w.max(frame_h * aspect),
+        _ => frame_w.min(frame_h * aspect),
+    };
+    let height = width / aspect;
+
+    (
+        ((width / pixel_w).round() as u32).max(1),
+        ((height / pixel_h).round() as u32).max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_size() {
+        // A 4:3 camera in 80x24 cells of 1:2 is 80:48, so a little too tall
+        let ascii = |fit| content_size((640, 480), (80, 24), (80, 24), 2.0, fit);
+        assert_eq!(ascii(Fit::Crop), (80, 30));
+        assert_eq!(ascii(Fit::Letterbox), (64, 24));
+        assert_eq!(ascii(Fit::Stretch), (80, 24));
+
+        // Half blocks have square pixels, twice as many rows
+        assert_eq!(content_size((640, 480), (80, 48), (80, 24), 2.0, Fit::Letterbox), (64, 48));
+
+        // Square cells make a square picture square
+        assert_eq!(content_size((100, 100), (40, 20), (40, 20), 1.0, Fit::Crop), (40, 40));
+
+        // Nonsense aspects fall back to stretching rather than huge sizes
+        assert_eq!(content_size((640, 480), (80, 24), (80, 24), 0.0, Fit::Crop), (80, 24));
+    }
+
+    #[test]
+    fn test_parse_cell_aspect() {
+        assert_eq!(parse_cell_aspect("2.1").unwrap(), 2.1);
+        for bad in ["0", "-1", "NaN", "inf", "tall"] {
+            assert!(parse_cell_aspect(bad).is_err(), "{}", bad);
+        }
+    }
+}
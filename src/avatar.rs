@@ -0,0 +1,145 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{bounded, Receiver};
+
+use crate::ascii;
+use crate::aspect;
+use crate::image::RgbImage;
+use crate::message::AsciiFrame;
+use crate::source::FrameSource;
+use crate::webcam::WebcamConfig;
+
+/// How often the unchanging avatar is sent again, so that viewers who join
+/// later and keyframes after dropped deltas still get it
+const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Color of the glyphs of text avatars
+const TEXT_COLOR: (u8, u8, u8) = (229, 229, 229);
+
+/// Extensions of files read as ASCII art rather than decoded as images
+const TEXT_EXTENSIONS: [&str; 3] = ["txt", "asc", "nfo"];
+
+/// Frame of a picture, converted like a camera image with the capture's
+/// render mode, palette and fit
+pub fn image_frame(image: &RgbImage, config: &WebcamConfig) -> AsciiFrame {
+    let (pixel_width, pixel_height) = config.convert.mode.pixel_size(config.width, config.height);
+    let (width, height) = aspect::content_size(
+        (image.width as u32, image.height as u32),
+        (pixel_width, pixel_height),
+        (config.width, config.height),
+        config.cell_aspect,
+        config.fit,
+    );
+    let image = image
+        .resize(width as usize, height as usize)
+        .centered(pixel_width as usize, pixel_height as usize);
+    ascii::convert(&image, config.width, config.height, &config.convert)
+}
+
+/// Frame of hand-drawn ASCII art, centered and cut to the frame size
+pub fn text_frame(text: &str, width: u16, height: u16) -> AsciiFrame {
+    let lines: Vec<Vec<char>> = text
+        .lines()
+        .map(|line| line.replace('\t', "    ").trim_end().chars().collect())
+        .collect();
+    let art_width = lines.iter().map(Vec::len).max().unwrap_or(0);
+    let left = (width as usize).saturating_sub(art_width) / 2;
+    let top = (height as usize).saturating_sub(lines.len()) / 2;
+
+    let (r, g, b) = TEXT_COLOR;
+    let mut frame = AsciiFrame::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let ch = (y as usize)
+                .checked_sub(top)
+                .and_then(|row| lines.get(row))
+                .and_then(|line| line.get((x as usize).checked_sub(left)?))
+                .copied()
+                .unwrap_or(' ');
+            frame.set_cell(x, y, ch, r, g, b);
+        }
+    }
+    frame
+}
+
+/// Still picture sent in place of video, for users without a camera
+///
+/// The picture is an image file (PNG, JPEG or anything else FFmpeg decodes)
+/// or, for text files (`.txt`, `.asc`, `.nfo`), ASCII art used as is. It is
+/// converted once and sent again every `RESEND_INTERVAL`.
+pub struct AvatarSource {
+    receiver: Receiver<AsciiFrame>,
+}
+
+impl AvatarSource {
+    pub fn start(config: &WebcamConfig, path: &Path) -> Result<Self> {
+        let is_text = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        let frame = if is_text {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read avatar {}", path.display()))?;
+            text_frame(&text, config.width, config.height)
+        } else {
+            image_frame(&RgbImage::load(path)?, config)
+        };
+
+        let (tx, rx) = bounded::<AsciiFrame>(1);
+        thread::spawn(move || {
+            while tx.send(frame.clone()).is_ok() {
+                thread::sleep(RESEND_INTERVAL);
+            }
+        });
+
+        Ok(Self { receiver: rx })
+    }
+}
+
+impl FrameSource for AvatarSource {
+    fn try_recv(&self) -> Option<AsciiFrame> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aspect::Fit;
+    use crate::palette::Palette;
+
+    #[test]
+    fn test_text_frame() {
+        let frame = text_frame(" /\\_/\\\n( o.o )\n > ^ <\n", 11, 5);
+        let row = |y| -> String { (0..11).map(|x| frame.get_cell(x, y).unwrap().0).collect() };
+        assert_eq!(row(0), "           ");
+        assert_eq!(row(1), "   /\\_/\\   ");
+        assert_eq!(row(2), "  ( o.o )  ");
+        assert_eq!(row(3), "   > ^ <   ");
+
+        // Art larger than the frame is cut
+        let frame = text_frame("abcdef\nghijkl", 3, 1);
+        assert_eq!(frame.get_cell(0, 0), Some(('a', 229, 229, 229)));
+    }
+
+    #[test]
+    fn test_image_frame_keeps_aspect() {
+        // A white square letterboxed into 40x10 cells of 1:2 is 20 cells wide
+        let mut image = RgbImage::new(8, 8);
+        image.data.fill(255);
+        let config = WebcamConfig {
+            width: 40,
+            height: 10,
+            fit: Fit::Letterbox,
+            cell_aspect: 2.0,
+            ..WebcamConfig::default()
+        };
+        let frame = image_frame(&image, &config);
+        let palette = Palette::standard();
+        assert_eq!(frame.get_cell(20, 5).unwrap().0, palette.glyph_for(255));
+        assert_eq!(frame.get_cell(2, 5).unwrap().0, palette.glyph_for(0));
+    }
+}
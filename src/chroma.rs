@@ -0,0 +1,278 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use crate::filter::FrameFilter;
+use crate::image::RgbImage;
+use crate::message::AsciiFrame;
+
+/// Width of the band past the tolerance over which pixels blend from
+/// background to camera, as a share of the tolerance
+const SOFTNESS: f32 = 0.5;
+
+/// What keyed pixels are replaced with
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyBackground {
+    /// Blank cells, which the UI leaves showing the terminal background
+    Transparent,
+    Color(u8, u8, u8),
+    /// Image file, stretched over the frame
+    Image(PathBuf),
+}
+
+impl std::str::FromStr for KeyBackground {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if matches!(s.to_ascii_lowercase().as_str(), "transparent" | "none") {
+            return Ok(Self::Transparent);
+        }
+        match parse_color(s) {
+            Ok((r, g, b)) => Ok(Self::Color(r, g, b)),
+            // A malformed `#` color is a typo, not an image path
+            Err(e) if s.starts_with('#') => Err(e),
+            Err(_) => Ok(Self::Image(PathBuf::from(s))),
+        }
+    }
+}
+
+/// Green screen settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChromaKey {
+    /// Color of the screen behind the user
+    pub color: (u8, u8, u8),
+    /// Largest chroma distance from `color` that is keyed out
+    pub tolerance: f32,
+    pub background: KeyBackground,
+}
+
+impl Default for ChromaKey {
+    fn default() -> Self {
+        Self {
+            color: (0, 177, 64),
+            tolerance: 40.0,
+            background: KeyBackground::Transparent,
+        }
+    }
+}
+
+/// Parse a key color: `green`, `blue` or hex such as `#00b140`
+pub fn parse_color(s: &str) -> Result<(u8, u8, u8)> {
+    match s.to_ascii_lowercase().as_str() {
+        "green" => return Ok((0, 177, 64)),
+        "blue" => return Ok((0, 71, 187)),
+        _ => {}
+    }
+
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(anyhow!("Colors are written as #rrggbb, not {}", s));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("Invalid color: {}", s));
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Parse a key tolerance, a chroma distance from 0 up
+pub fn parse_tolerance(s: &str) -> Result<f32> {
+    let tolerance: f32 = s.parse()?;
+    if !(tolerance.is_finite() && tolerance >= 0.0) {
+        return Err(anyhow!("Key tolerance must be a number from 0 up, not {}", s));
+    }
+    Ok(tolerance)
+}
+
+/// Blue and red difference (Cb, Cr) of a color, BT.601
+///
+/// Keying on chroma alone keeps shadows on the screen keyed, since they only
+/// change the brightness.
+fn chroma(r: u8, g: u8, b: u8) -> (f32, f32) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    (
+        -0.168736 * r - 0.331264 * g + 0.5 * b,
+        0.5 * r - 0.418688 * g - 0.081312 * b,
+    )
+}
+
+/// Filter replacing the key color in captured images
+///
+/// Runs last in the RGB stage, so the mask it keeps for transparent keying
+/// lines up with the image after any mirroring or rotation.
+pub struct ChromaKeyer {
+    key: ChromaKey,
+    key_chroma: (f32, f32),
+    /// Background image as loaded, and scaled to the last frame size
+    image: Option<RgbImage>,
+    scaled: Option<RgbImage>,
+    /// Keyed pixels of the last image, for blanking cells
+    mask: Vec<bool>,
+    mask_size: (usize, usize),
+}
+
+impl ChromaKeyer {
+    pub fn new(key: ChromaKey) -> Result<Self> {
+        let image = match &key.background {
+            KeyBackground::Image(path) => Some(RgbImage::load(path)?),
+            _ => None,
+        };
+        let (r, g, b) = key.color;
+        Ok(Self {
+            key_chroma: chroma(r, g, b),
+            key,
+            image,
+            scaled: None,
+            mask: Vec::new(),
+            mask_size: (0, 0),
+        })
+    }
+
+    /// How much of a pixel is kept, 0.0 for the key color up to 1.0 past the soft band
+    fn alpha(&self, r: u8, g: u8, b: u8) -> f32 {
+        let (cb, cr) = chroma(r, g, b);
+        let distance = ((cb - self.key_chroma.0).powi(2) + (cr - self.key_chroma.1).powi(2)).sqrt();
+        let band = (self.key.tolerance * SOFTNESS).max(1.0);
+        ((distance - self.key.tolerance) / band).clamp(0.0, 1.0)
+    }
+
+    /// Background pixel at (x, y) of an image of the given size
+    fn background(&mut self, x: usize, y: usize, width: usize, height: usize) -> (u8, u8, u8) {
+        match self.key.background {
+            KeyBackground::Transparent => (0, 0, 0),
+            KeyBackground::Color(r, g, b) => (r, g, b),
+            KeyBackground::Image(_) => {
+                let stale = self
+                    .scaled
+                    .as_ref()
+                    .is_none_or(|scaled| (scaled.width, scaled.height) != (width, height));
+                if stale {
+                    self.scaled = self.image.as_ref().map(|image| image.resize(width, height));
+                }
+                self.scaled.as_ref().map_or((0, 0, 0), |scaled| scaled.pixel(x, y))
+            }
+        }
+    }
+}
+
+impl FrameFilter for ChromaKeyer {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        let (width, height) = (image.width, image.height);
+        self.mask.clear();
+        self.mask_size = (width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = image.pixel(x, y);
+                let alpha = self.alpha(r, g, b);
+                self.mask.push(alpha < 0.5);
+                if alpha < 1.0 {
+                    let (br, bg, bb) = self.background(x, y, width, height);
+                    let blend = |c: u8, bc: u8| (bc as f32 + (c as f32 - bc as f32) * alpha).round() as u8;
+                    image.set_pixel(x, y, (blend(r, br), blend(g, bg), blend(b, bb)));
+                }
+            }
+        }
+    }
+
+    fn apply_frame(&mut self, frame: &mut AsciiFrame) {
+        let (width, height) = self.mask_size;
+        if self.key.background != KeyBackground::Transparent || width == 0 || height == 0 {
+            return;
+        }
+
+        // Each cell covers a block of pixels; it is blanked when most are keyed
+        let (cols, rows) = (frame.width as usize, frame.height as usize);
+        for cy in 0..rows {
+            let (y0, y1) = (cy * height / rows, ((cy + 1) * height / rows).max(cy * height / rows + 1));
+            for cx in 0..cols {
+                let (x0, x1) = (cx * width / cols, ((cx + 1) * width / cols).max(cx * width / cols + 1));
+                let mut keyed = 0;
+                let mut total = 0;
+                for y in y0..y1.min(height) {
+                    for x in x0..x1.min(width) {
+                        keyed += self.mask[y * width + x] as usize;
+                        total += 1;
+                    }
+                }
+                if keyed * 2 > total {
+                    frame.set_cell(cx as u16, cy as u16, '\0', 0, 0, 0);
+                    frame.set_background(cx as u16, cy as u16, 0, 0, 0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: (u8, u8, u8) = (10, 180, 70);
+    const SKIN: (u8, u8, u8) = (224, 172, 140);
+
+    /// Green screen on the left half, a face on the right
+    fn scene() -> RgbImage {
+        let mut image = RgbImage::new(4, 2);
+        for y in 0..2 {
+            for x in 0..4 {
+                image.set_pixel(x, y, if x < 2 { SCREEN } else { SKIN });
+            }
+        }
+        image
+    }
+
+    fn keyer(background: KeyBackground) -> ChromaKeyer {
+        ChromaKeyer::new(ChromaKey {
+            background,
+            ..ChromaKey::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_solid_background() {
+        let mut image = scene();
+        keyer(KeyBackground::Color(20, 20, 90)).apply_rgb(&mut image);
+        assert_eq!(image.pixel(0, 0), (20, 20, 90));
+        assert_eq!(image.pixel(3, 1), SKIN);
+
+        // A dark shadow on the screen is still keyed, gray clothes are not
+        let mut image = RgbImage::new(2, 1);
+        image.set_pixel(0, 0, (5, 120, 45));
+        image.set_pixel(1, 0, (128, 128, 128));
+        keyer(KeyBackground::Color(20, 20, 90)).apply_rgb(&mut image);
+        assert_eq!(image.pixel(0, 0), (20, 20, 90));
+        assert_eq!(image.pixel(1, 0), (128, 128, 128));
+    }
+
+    #[test]
+    fn test_transparent_cells() {
+        let mut keyer = keyer(KeyBackground::Transparent);
+        let mut image = scene();
+        keyer.apply_rgb(&mut image);
+
+        let mut frame = AsciiFrame::new(2, 1);
+        frame.set_cell(0, 0, '#', 1, 2, 3);
+        frame.set_cell(1, 0, '#', 1, 2, 3);
+        keyer.apply_frame(&mut frame);
+        assert_eq!(frame.get_cell(0, 0), Some(('\0', 0, 0, 0)));
+        assert_eq!(frame.get_cell(1, 0), Some(('#', 1, 2, 3)));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_color("#00B140").unwrap(), (0, 177, 64));
+        assert_eq!(parse_color("blue").unwrap(), (0, 71, 187));
+        assert!(parse_color("#0b1").is_err());
+        assert_eq!(parse_tolerance("0").unwrap(), 0.0);
+        assert_eq!(parse_tolerance("55.5").unwrap(), 55.5);
+        for bad in ["-1", "NaN", "inf", "wide"] {
+            assert!(parse_tolerance(bad).is_err(), "{}", bad);
+        }
+        assert_eq!("none".parse::<KeyBackground>().unwrap(), KeyBackground::Transparent);
+        assert_eq!("#ff0000".parse::<KeyBackground>().unwrap(), KeyBackground::Color(255, 0, 0));
+        assert_eq!("blue".parse::<KeyBackground>().unwrap(), KeyBackground::Color(0, 71, 187));
+        assert_eq!(
+            "room.png".parse::<KeyBackground>().unwrap(),
+            KeyBackground::Image(PathBuf::from("room.png"))
+        );
+    }
+}
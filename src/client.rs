@@ -1,7 +1,17 @@
-use crate::message::{AsciiFrame, Message};
-use crate::webcam::{WebcamCapture, WebcamConfig};
-use anyhow::Result;
+use crate::ascii::LocalRender;
+use crate::compress::{Compression, CompressionStats};
+use crate::delta::{DeltaEncoder, EncodedFrame};
+use crate::message::{AsciiFrame, CellFormat, Encoding, Message, PixelFormat, PixelFrame};
+use crate::palette::Palette;
+use crate::privacy::PrivacyMode;
+use crate::source::{FrameSource, SourceSpec};
+use crate::webcam::{WebcamCommand, WebcamConfig};
+use crate::wire;
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Sender;
 use futures::{SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use parking_lot::RwLock;
@@ -15,8 +25,29 @@ pub struct TermIOClient {
     pub username: String,
     pub user_id: String,
     pub server_url: String,
+    /// Encoding requested from the server and used for outgoing messages
+    pub encoding: Encoding,
+    /// Compression of frame data in both directions (binary encoding only)
+    pub compression: Compression,
+    /// Where our own video comes from
+    pub source: SourceSpec,
+    /// Capture settings for our own video
+    pub webcam_config: WebcamConfig,
+    /// Control channel of the running source, set once connected if it has one
+    pub webcam_commands: Option<Sender<WebcamCommand>>,
+    /// Pixel frames to ask for, for terminals that draw pixel graphics or
+    /// viewers that render cells themselves
+    pub pixels: Option<PixelFormat>,
+    /// Renderer for users' pixel frames picked by this viewer, `None` shows the
+    /// cells each sender chose
+    pub render: Option<LocalRender>,
     pub connected_users: Arc<RwLock<Vec<String>>>,
     pub last_frames: Arc<RwLock<std::collections::HashMap<String, AsciiFrame>>>,
+    /// Last frame of each user who left, until the UI takes it to fade their
+    /// tile out
+    pub left_frames: Arc<RwLock<HashMap<String, AsciiFrame>>>,
+    /// Latest pixel frame of each user, received only when `pixels` is set
+    pub last_pixels: Arc<RwLock<HashMap<String, PixelFrame>>>,
     pub chat_messages: Arc<RwLock<Vec<(String, String)>>>, // (username, message)
     pub ws_sender: Arc<Mutex<Option<WsSender>>>,
 }
@@ -28,15 +59,30 @@ impl TermIOClient {
             username,
             user_id: String::new(),
             server_url,
+            encoding: Encoding::Binary,
+            compression: Compression::Deflate,
+            source: SourceSpec::default(),
+            webcam_config: WebcamConfig::default(),
+            webcam_commands: None,
+            pixels: None,
+            render: None,
             connected_users: Arc::new(RwLock::new(Vec::new())),
             last_frames: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            left_frames: Arc::new(RwLock::new(HashMap::new())),
+            last_pixels: Arc::new(RwLock::new(HashMap::new())),
             chat_messages: Arc::new(RwLock::new(Vec::new())),
             ws_sender: Arc::new(Mutex::new(None)),
         }
     }
 
-    /// Connect to the TermIO server
+    /// Connect to the TermIO server, sending video from `source`
     pub async fn connect(&mut self) -> Result<()> {
+        let source = self.source.start(&self.webcam_config)?;
+        self.connect_with(source).await
+    }
+
+    /// Connect to the TermIO server, sending video from any frame source
+    pub async fn connect_with(&mut self, source: Box<dyn FrameSource>) -> Result<()> {
         let (ws_stream, _) = connect_async(&self.server_url).await?;
         tracing::info!("Connected to TermIO server at {}", self.server_url);
 
@@ -45,36 +91,59 @@ impl TermIOClient {
         // Store the sender in Arc<Mutex<>> so it can be shared
         *self.ws_sender.lock().await = Some(ws_tx);
 
-        // Send join message
+        // Send join message. Always JSON, since the encoding is not agreed yet
         let join_msg = Message::Join {
             username: self.username.clone(),
+            encoding: self.encoding,
+            deltas: true,
+            compression: self.compression,
+            pixels: self.pixels.is_some(),
+            pixel_format: self.pixels.unwrap_or_default(),
+            cell_format: CellFormat {
+                unicode: true,
+                background: true,
+            },
         };
-        {
-            let mut sender = self.ws_sender.lock().await;
-            if let Some(ref mut tx) = sender.as_mut() {
-                tx.send(WsMessage::Text(serde_json::to_string(&join_msg)?)).await?;
-            }
-        }
+        send_message(&self.ws_sender, &join_msg, Encoding::Json, Compression::None).await?;
+
+        // Deltas only make sense on top of a keyframe, so ask for everyone's
+        let keyframe_msg = Message::KeyframeRequest {
+            user_id: String::new(),
+        };
+        send_message(&self.ws_sender, &keyframe_msg, self.encoding, self.compression).await?;
 
-        // Start webcam capture
-        let webcam_config = WebcamConfig::default();
-        let webcam = WebcamCapture::start(webcam_config)?;
+        self.webcam_commands = source.commands();
 
         // Spawn tasks for handling messages and webcam
         let connected_users = Arc::clone(&self.connected_users);
         let last_frames = Arc::clone(&self.last_frames);
         let last_frames_webcam = Arc::clone(&self.last_frames); // Clone for webcam task
+        let left_frames = Arc::clone(&self.left_frames);
+        let last_pixels = Arc::clone(&self.last_pixels);
+        let last_pixels_webcam = Arc::clone(&self.last_pixels);
+        let webcam_commands = source.commands();
         let chat_messages = Arc::clone(&self.chat_messages);
         let username = self.username.clone();
         let username_webcam = username.clone(); // Clone for webcam task
+        let encoding = self.encoding;
+        let compression = self.compression;
         let ws_sender_clone = Arc::clone(&self.ws_sender);
+        let ws_sender_receiver = Arc::clone(&self.ws_sender);
+        // Set when the server asks for a keyframe of our stream
+        let force_keyframe = Arc::new(AtomicBool::new(false));
+        let force_keyframe_webcam = Arc::clone(&force_keyframe);
 
         // Receiver task
         tokio::spawn(async move {
+            // Sequence number of each user's frame in `last_frames`
+            let mut seqs: HashMap<String, u32> = HashMap::new();
+            // Users we asked a keyframe for and are still waiting on
+            let mut awaiting_keyframe: HashSet<String> = HashSet::new();
+
             while let Some(msg_result) = ws_rx.next().await {
                 match msg_result {
-                    Ok(WsMessage::Text(text)) => {
-                        if let Ok(msg) = serde_json::from_str::<Message>(&text) {
+                    Ok(ws_msg @ (WsMessage::Text(_) | WsMessage::Binary(_))) => {
+                        if let Ok(msg) = wire::from_ws(&ws_msg) {
                             match msg {
                                 Message::UserList(users) => {
                                     let mut users_guard = connected_users.write();
@@ -83,13 +152,62 @@ impl TermIOClient {
                                         users_guard.push(user.username);
                                     }
                                 }
+                                // Our own frames are stored directly by the webcam task
+                                Message::Frame { username: frame_user, .. }
+                                | Message::FrameDelta { username: frame_user, .. }
+                                | Message::PixelFrame { username: frame_user, .. }
+                                    if frame_user == username => {}
                                 Message::Frame {
                                     username: frame_user,
                                     frame: frame_data,
+                                    seq,
                                     ..
                                 } => {
                                     let mut frames = last_frames.write();
-                                    frames.insert(frame_user, frame_data);
+                                    frames.insert(frame_user.clone(), frame_data);
+                                    awaiting_keyframe.remove(&frame_user);
+                                    seqs.insert(frame_user, seq);
+                                }
+                                Message::FrameDelta {
+                                    user_id: frame_user_id,
+                                    username: frame_user,
+                                    delta,
+                                } => {
+                                    let applied = {
+                                        let mut frames = last_frames.write();
+                                        match (frames.get_mut(&frame_user), seqs.get(&frame_user)) {
+                                            (Some(frame), Some(&seq)) if seq == delta.base_seq => {
+                                                delta.apply(frame).is_ok()
+                                            }
+                                            _ => false,
+                                        }
+                                    };
+
+                                    if applied {
+                                        seqs.insert(frame_user, delta.seq);
+                                    } else if awaiting_keyframe.insert(frame_user.clone()) {
+                                        // Ask once per gap; the next keyframe restores the sequence
+                                        tracing::debug!("Lost sync with {}, requesting keyframe", frame_user);
+                                        let request = Message::KeyframeRequest {
+                                            user_id: frame_user_id,
+                                        };
+                                        let _ = send_message(&ws_sender_receiver, &request, encoding, compression).await;
+                                    }
+                                }
+                                Message::PixelFrame {
+                                    username: frame_user,
+                                    frame,
+                                    ..
+                                } => {
+                                    last_pixels.write().insert(frame_user, frame);
+                                }
+                                Message::PixelDemand { wanted, format } => {
+                                    if let Some(commands) = &webcam_commands {
+                                        let _ = commands.try_send(WebcamCommand::SetPixels(wanted.then_some(format)));
+                                    }
+                                }
+                                Message::KeyframeRequest { .. } => {
+                                    force_keyframe.store(true, Ordering::Relaxed);
                                 }
                                 Message::Chat {
                                     username: chat_user,
@@ -104,6 +222,12 @@ impl TermIOClient {
                                 }
                                 Message::UserLeft { username: left, .. } => {
                                     tracing::info!("{} left the chat", left);
+                                    // Their tile fades out from the last frame they sent
+                                    if let Some(frame) = last_frames.write().remove(&left) {
+                                        left_frames.write().insert(left.clone(), frame);
+                                    }
+                                    last_pixels.write().remove(&left);
+                                    seqs.remove(&left);
                                 }
                                 _ => {}
                             }
@@ -122,37 +246,68 @@ impl TermIOClient {
             }
         });
 
-        // Webcam sender task (runs in background)
+        // Video sender task (runs in background)
         let ws_sender_webcam = Arc::clone(&ws_sender_clone);
         tokio::spawn(async move {
+            let mut encoder = DeltaEncoder::default();
+            let mut stats = CompressionStats::default();
+
             loop {
-                if let Some(frame) = webcam.try_recv() {
+                if let Some(frame) = source.try_recv() {
                     // Store OWN frame locally so we can see it in the UI
                     {
                         let mut frames = last_frames_webcam.write();
                         frames.insert(username_webcam.clone(), frame.clone());
                     }
 
-                    let frame_msg = Message::Frame {
-                        user_id: String::new(),
-                        username: username_webcam.clone(),
-                        frame: frame.clone(),
+                    if force_keyframe_webcam.swap(false, Ordering::Relaxed) {
+                        encoder.force_keyframe();
+                    }
+
+                    let frame_msg = match encoder.encode(&frame) {
+                        EncodedFrame::Key { seq, frame } => Message::Frame {
+                            user_id: String::new(),
+                            username: username_webcam.clone(),
+                            frame,
+                            seq,
+                        },
+                        EncodedFrame::Delta(delta) => Message::FrameDelta {
+                            user_id: String::new(),
+                            username: username_webcam.clone(),
+                            delta,
+                        },
                     };
 
                     // Send frame over WebSocket to server
-                    if let Ok(json) = serde_json::to_string(&frame_msg) {
-                        let mut sender = ws_sender_webcam.lock().await;
-                        if let Some(ref mut tx) = sender.as_mut() {
-                            if let Err(e) = tx.send(WsMessage::Text(json)).await {
-                                tracing::error!("Failed to send frame: {}", e);
-                                break;
+                    match send_message(&ws_sender_webcam, &frame_msg, encoding, compression).await {
+                        Ok(sent) => {
+                            if let (Some(raw), Encoding::Binary) = (frame_msg.cell_bytes(), encoding) {
+                                stats.record("uplink", compression, raw, sent);
                             }
                         }
+                        Err(e) => {
+                            tracing::error!("Failed to send frame: {}", e);
+                            break;
+                        }
                     }
 
                     tracing::debug!("Sent frame: {}x{}", frame.width, frame.height);
                 }
 
+                if let Some(pixels) = source.try_recv_pixels() {
+                    last_pixels_webcam.write().insert(username_webcam.clone(), pixels.clone());
+
+                    let pixel_msg = Message::PixelFrame {
+                        user_id: String::new(),
+                        username: username_webcam.clone(),
+                        frame: pixels,
+                    };
+                    if let Err(e) = send_message(&ws_sender_webcam, &pixel_msg, encoding, compression).await {
+                        tracing::error!("Failed to send pixel frame: {}", e);
+                        break;
+                    }
+                }
+
                 tokio::time::sleep(tokio::time::Duration::from_millis(33)).await;
             }
         });
@@ -160,6 +315,26 @@ impl TermIOClient {
         Ok(())
     }
 
+    /// Switch the palette of our running capture
+    pub fn set_palette(&mut self, palette: Palette) -> Result<()> {
+        let commands = self.webcam_commands.as_ref().ok_or_else(|| anyhow!("Source does not take commands"))?;
+        commands
+            .try_send(WebcamCommand::SetPalette(palette.clone()))
+            .map_err(|_| anyhow!("Webcam is not accepting commands"))?;
+        self.webcam_config.convert.palette = palette;
+        Ok(())
+    }
+
+    /// Switch how the running capture hides our static background
+    pub fn set_privacy(&mut self, mode: PrivacyMode) -> Result<()> {
+        let commands = self.webcam_commands.as_ref().ok_or_else(|| anyhow!("Source does not take commands"))?;
+        commands
+            .try_send(WebcamCommand::SetPrivacy(mode))
+            .map_err(|_| anyhow!("Webcam is not accepting commands"))?;
+        self.webcam_config.privacy = mode;
+        Ok(())
+    }
+
     /// Send a chat message
     pub async fn send_chat(&self, content: String) -> Result<()> {
         let msg = Message::Chat {
@@ -168,13 +343,24 @@ impl TermIOClient {
             content,
         };
 
-        if let Ok(json) = serde_json::to_string(&msg) {
-            let mut sender = self.ws_sender.lock().await;
-            if let Some(ref mut tx) = sender.as_mut() {
-                tx.send(WsMessage::Text(json)).await?;
-            }
-        }
+        send_message(&self.ws_sender, &msg, self.encoding, self.compression).await.map(|_| ())
+    }
+}
 
-        Ok(())
+/// Send a message on the shared WebSocket sender, if connected
+///
+/// Returns the number of bytes handed to the socket.
+async fn send_message(
+    ws_sender: &Mutex<Option<WsSender>>,
+    msg: &Message,
+    encoding: Encoding,
+    compression: Compression,
+) -> Result<usize> {
+    let out = wire::to_ws(msg, encoding, compression)?;
+    let len = out.len();
+    let mut sender = ws_sender.lock().await;
+    if let Some(tx) = sender.as_mut() {
+        tx.send(out).await?;
     }
+    Ok(len)
 }
@@ -0,0 +1,295 @@
+use std::sync::OnceLock;
+
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+
+use crate::exposure::to_linear;
+
+/// Channel levels of the xterm 6x6x6 color cube, indices 16..=231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// xterm's default values for the 16 ANSI colors
+///
+/// Terminals let users theme these, so they are a best guess; the 256 color
+/// mode avoids them for that reason.
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Named colors in ANSI order, sent as the basic SGR codes that every color
+/// terminal understands
+const ANSI_NAMES: [Color; 16] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Gray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+/// Colors a terminal can display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDepth {
+    /// 24-bit RGB
+    #[default]
+    TrueColor,
+    /// xterm 256 color palette
+    Ansi256,
+    /// The 16 basic ANSI colors
+    Ansi16,
+}
+
+impl std::str::FromStr for ColorDepth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" | "rgb" => Ok(Self::TrueColor),
+            "256" | "ansi256" => Ok(Self::Ansi256),
+            "16" | "ansi" | "ansi16" => Ok(Self::Ansi16),
+            other => Err(anyhow::anyhow!("Unknown color depth: {}", other)),
+        }
+    }
+}
+
+impl ColorDepth {
+    /// Color depth of the terminal we run in, judged from the environment
+    pub fn detect() -> Self {
+        Self::detect_from(|name| std::env::var(name).ok())
+    }
+
+    /// Detection with environment lookups supplied by the caller
+    ///
+    /// `TERMIO_COLORS` overrides everything, then `COLORTERM` announces true
+    /// color, then terminals known by `TERM_PROGRAM` or `WT_SESSION`, and last
+    /// the `TERM` name. Terminal.app claims `xterm-256color` and really is
+    /// limited to 256 colors, so it never gets true color.
+    pub fn detect_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        if let Some(depth) = var("TERMIO_COLORS").and_then(|v| v.parse().ok()) {
+            return depth;
+        }
+
+        let term_program = var("TERM_PROGRAM").unwrap_or_default();
+        if term_program == "Apple_Terminal" {
+            return Self::Ansi256;
+        }
+
+        let colorterm = var("COLORTERM").unwrap_or_default().to_ascii_lowercase();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return Self::TrueColor;
+        }
+        if matches!(term_program.as_str(), "iTerm.app" | "WezTerm" | "vscode" | "ghostty")
+            || var("WT_SESSION").is_some()
+        {
+            return Self::TrueColor;
+        }
+
+        let term = var("TERM").unwrap_or_default().to_ascii_lowercase();
+        if term.ends_with("-direct") || term.contains("truecolor") {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else {
+            Self::Ansi16
+        }
+    }
+
+    /// Closest displayable color to an RGB value
+    pub fn map(self, r: u8, g: u8, b: u8) -> Color {
+        match self {
+            Self::TrueColor => Color::Rgb(r, g, b),
+            Self::Ansi256 => Color::Indexed(nearest_256(r, g, b)),
+            Self::Ansi16 => ANSI_NAMES[nearest_16(r, g, b) as usize],
+        }
+    }
+
+    /// Convert every RGB color in an area of a buffer
+    ///
+    /// Runs after tile effects, which work on RGB values.
+    pub fn downgrade(self, buf: &mut Buffer, area: Rect) {
+        if self == Self::TrueColor {
+            return;
+        }
+        let area = area.intersection(buf.area);
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = &mut buf[(x, y)];
+                if let Color::Rgb(r, g, b) = cell.fg {
+                    cell.fg = self.map(r, g, b);
+                }
+                if let Color::Rgb(r, g, b) = cell.bg {
+                    cell.bg = self.map(r, g, b);
+                }
+            }
+        }
+    }
+}
+
+/// CIE L*a*b* coordinates of an sRGB color (D65 white)
+///
+/// Euclidean distance in Lab follows perceived difference far better than in
+/// RGB, where steps in dark colors count as much as in bright ones.
+fn to_lab(r: u8, g: u8, b: u8) -> [f32; 3] {
+    let (r, g, b) = (to_linear(r), to_linear(g), to_linear(b));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+/// Lab coordinates of the xterm 256 color palette, built once
+fn palette_256() -> &'static [[f32; 3]; 256] {
+    static LAB: OnceLock<[[f32; 3]; 256]> = OnceLock::new();
+    LAB.get_or_init(|| {
+        let mut lab = [[0.0; 3]; 256];
+        for (i, entry) in lab.iter_mut().enumerate() {
+            let (r, g, b) = xterm_rgb(i as u8);
+            *entry = to_lab(r, g, b);
+        }
+        lab
+    })
+}
+
+/// RGB value of an entry of the xterm 256 color palette
+pub fn xterm_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI_16[index as usize],
+        16..=231 => {
+            let i = (index - 16) as usize;
+            (CUBE_LEVELS[i / 36], CUBE_LEVELS[i / 6 % 6], CUBE_LEVELS[i % 6])
+        }
+        _ => {
+            let v = 8 + 10 * (index - 232);
+            (v, v, v)
+        }
+    }
+}
+
+/// Index of the palette entry in `range` perceptually closest to a color
+fn nearest(range: std::ops::Range<usize>, r: u8, g: u8, b: u8) -> u8 {
+    let lab = to_lab(r, g, b);
+    let palette = palette_256();
+    range
+        .min_by(|&x, &y| distance(&palette[x], &lab).total_cmp(&distance(&palette[y], &lab)))
+        .expect("palette range is not empty") as u8
+}
+
+/// Nearest entry of the xterm 256 color palette, from the color cube and the
+/// gray ramp only
+pub fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    nearest(16..256, r, g, b)
+}
+
+/// Nearest of the 16 ANSI colors
+pub fn nearest_16(r: u8, g: u8, b: u8) -> u8 {
+    nearest(0..16, r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_256() {
+        // Exact cube and ramp colors map onto themselves
+        assert_eq!(nearest_256(0, 0, 0), 16);
+        assert_eq!(nearest_256(255, 255, 255), 231);
+        assert_eq!(nearest_256(255, 0, 0), 196);
+        assert_eq!(nearest_256(95, 135, 175), 67);
+        assert_eq!(nearest_256(128, 128, 128), 244);
+        // Near grays prefer the finer gray ramp over the cube
+        assert_eq!(nearest_256(60, 60, 62), 237);
+        // Nothing lands in the user-themed first 16
+        for v in (0..=255).step_by(5) {
+            assert!(nearest_256(v, 255 - v, v / 2) >= 16);
+        }
+    }
+
+    #[test]
+    fn test_nearest_16() {
+        assert_eq!(ANSI_NAMES[nearest_16(0, 0, 0) as usize], Color::Black);
+        assert_eq!(ANSI_NAMES[nearest_16(250, 20, 20) as usize], Color::LightRed);
+        assert_eq!(ANSI_NAMES[nearest_16(180, 10, 10) as usize], Color::Red);
+        assert_eq!(ANSI_NAMES[nearest_16(140, 140, 140) as usize], Color::DarkGray);
+        assert_eq!(ANSI_NAMES[nearest_16(245, 245, 245) as usize], Color::White);
+        assert_eq!(ANSI_NAMES[nearest_16(10, 10, 200) as usize], Color::Blue);
+    }
+
+    #[test]
+    fn test_downgrade_buffer() {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 2, 1));
+        buf[(0, 0)].set_fg(Color::Rgb(255, 0, 0)).set_bg(Color::Rgb(0, 0, 0));
+        buf[(1, 0)].set_fg(Color::Green);
+
+        ColorDepth::Ansi256.downgrade(&mut buf, Rect::new(0, 0, 2, 1));
+        assert_eq!(buf[(0, 0)].fg, Color::Indexed(196));
+        assert_eq!(buf[(0, 0)].bg, Color::Indexed(16));
+        assert_eq!(buf[(1, 0)].fg, Color::Green);
+        assert_eq!(buf[(1, 0)].bg, Color::Reset);
+
+        assert_eq!(ColorDepth::TrueColor.map(1, 2, 3), Color::Rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn test_detect() {
+        let detect = |vars: &[(&str, &str)]| {
+            ColorDepth::detect_from(|name| {
+                vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+            })
+        };
+
+        assert_eq!(detect(&[("COLORTERM", "truecolor"), ("TERM", "xterm")]), ColorDepth::TrueColor);
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), ColorDepth::Ansi256);
+        assert_eq!(detect(&[("TERM", "xterm-direct")]), ColorDepth::TrueColor);
+        assert_eq!(detect(&[("TERM", "linux")]), ColorDepth::Ansi16);
+        assert_eq!(detect(&[]), ColorDepth::Ansi16);
+        assert_eq!(detect(&[("WT_SESSION", "1")]), ColorDepth::TrueColor);
+        assert_eq!(
+            detect(&[("TERM_PROGRAM", "Apple_Terminal"), ("TERM", "xterm-256color")]),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(
+            detect(&[("TERMIO_COLORS", "16"), ("COLORTERM", "truecolor")]),
+            ColorDepth::Ansi16
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{bail, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Compression applied to frame cell data, chosen per connection at join time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Run-length encoding of identical cells
+    Rle,
+    /// Run-length encoding followed by deflate
+    Deflate,
+}
+
+impl Compression {
+    /// Identifier used in the binary wire header
+    pub fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Rle => 1,
+            Self::Deflate => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Rle),
+            2 => Ok(Self::Deflate),
+            other => bail!("Unknown compression id: {}", other),
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(Self::None),
+            "rle" => Ok(Self::Rle),
+            "deflate" | "zlib" => Ok(Self::Deflate),
+            other => Err(anyhow::anyhow!("Unknown compression: {}", other)),
+        }
+    }
+}
+
+/// Longest run a single RLE control byte can describe
+const MAX_RUN: usize = 128;
+
+/// Compress cell data made of `cell_size`-byte cells with the given mode
+pub fn compress(data: &[u8], mode: Compression, cell_size: usize) -> Vec<u8> {
+    match mode {
+        Compression::None => data.to_vec(),
+        Compression::Rle => rle_encode(data, cell_size),
+        Compression::Deflate => {
+            // Fastest level: frames are small and latency matters more than ratio
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder
+                .write_all(&rle_encode(data, cell_size))
+                .and_then(|_| encoder.finish())
+                .expect("deflate into a Vec cannot fail")
+        }
+    }
+}
+
+/// Reverse `compress`, failing once the output grows past `limit` bytes
+///
+/// Peers choose what they send, so the limit must come from the message
+/// header: without it a few KB of runs or deflate expand to gigabytes.
+pub fn decompress(data: &[u8], mode: Compression, cell_size: usize, limit: usize) -> Result<Vec<u8>> {
+    match mode {
+        Compression::None => {
+            if data.len() > limit {
+                bail!("Cell data is {} bytes, more than the {} expected", data.len(), limit);
+            }
+            Ok(data.to_vec())
+        }
+        Compression::Rle => rle_decode(data, cell_size, limit),
+        Compression::Deflate => {
+            // RLE output is at most one control byte per cell larger than its input
+            let rle_limit = limit + limit / cell_size.max(1) + 1;
+            let mut rle = Vec::new();
+            DeflateDecoder::new(data)
+                .take(rle_limit as u64 + 1)
+                .read_to_end(&mut rle)?;
+            if rle.len() > rle_limit {
+                bail!("Deflated cell data expands past {} bytes", rle_limit);
+            }
+            rle_decode(&rle, cell_size, limit)
+        }
+    }
+}
+
+/// Run-length encode whole cells
+///
+/// Each block starts with a control byte: `0x80 | (n - 1)` is followed by one
+/// cell repeated `n` times, `n - 1` is followed by `n` literal cells.
+fn rle_encode(data: &[u8], cell_size: usize) -> Vec<u8> {
+    let cells: Vec<&[u8]> = data.chunks(cell_size).collect();
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let mut literals_start = 0;
+    let mut i = 0;
+
+    let flush_literals = |out: &mut Vec<u8>, from: usize, to: usize| {
+        for chunk in cells[from..to].chunks(MAX_RUN) {
+            out.push((chunk.len() - 1) as u8);
+            for cell in chunk {
+                out.extend_from_slice(cell);
+            }
+        }
+    };
+
+    while i < cells.len() {
+        let mut run = 1;
+        while i + run < cells.len() && run < MAX_RUN && cells[i + run] == cells[i] {
+            run += 1;
+        }
+
+        // Two equal cells cost the same either way, so only longer runs are worth it
+        if run >= 3 {
+            flush_literals(&mut out, literals_start, i);
+            out.push(0x80 | (run - 1) as u8);
+            out.extend_from_slice(cells[i]);
+            i += run;
+            literals_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    flush_literals(&mut out, literals_start, cells.len());
+
+    out
+}
+
+fn rle_decode(data: &[u8], cell_size: usize, limit: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity((data.len() * 2).min(limit));
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i] as usize;
+        i += 1;
+        let count = (control & 0x7f) + 1;
+        if out.len() + count * cell_size > limit {
+            bail!("RLE data expands past {} bytes", limit);
+        }
+
+        if control & 0x80 != 0 {
+            let Some(cell) = data.get(i..i + cell_size) else {
+                bail!("Truncated RLE run");
+            };
+            for _ in 0..count {
+                out.extend_from_slice(cell);
+            }
+            i += cell_size;
+        } else {
+            let len = count * cell_size;
+            let Some(cells) = data.get(i..i + len) else {
+                bail!("Truncated RLE literals");
+            };
+            out.extend_from_slice(cells);
+            i += len;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Frames between compression ratio log lines (~10s at 30 fps)
+const STATS_INTERVAL: u32 = 300;
+
+/// Running totals of frame payload size versus bytes put on the wire
+#[derive(Debug, Default)]
+pub struct CompressionStats {
+    raw_bytes: u64,
+    wire_bytes: u64,
+    frames: u32,
+}
+
+impl CompressionStats {
+    /// Record one sent frame and periodically log the achieved ratio
+    pub fn record(&mut self, label: &str, mode: Compression, raw: usize, wire: usize) {
+        self.raw_bytes += raw as u64;
+        self.wire_bytes += wire as u64;
+        self.frames += 1;
+
+        if self.frames >= STATS_INTERVAL {
+            let ratio = self.raw_bytes as f64 / self.wire_bytes.max(1) as f64;
+            tracing::info!(
+                "{}: {:?} compression {:.1}x ({} KB raw -> {} KB sent over {} frames)",
+                label,
+                mode,
+                ratio,
+                self.raw_bytes / 1024,
+                self.wire_bytes / 1024,
+                self.frames
+            );
+            *self = Self::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        // Dark background with a short bright stripe
+        let mut data = Vec::new();
+        for i in 0..200 {
+            if (50..55).contains(&i) {
+                data.extend_from_slice(&[b'@', 200, 180, i as u8]);
+            } else {
+                data.extend_from_slice(&[b' ', 5, 5, 5]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_roundtrip_all_modes() {
+        let data = sample();
+        for mode in [Compression::None, Compression::Rle, Compression::Deflate] {
+            let packed = compress(&data, mode, 4);
+            assert_eq!(decompress(&packed, mode, 4, data.len()).unwrap(), data, "{:?}", mode);
+            assert!(decompress(&packed, mode, 4, data.len() - 4).is_err(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_rle_shrinks_flat_frames() {
+        let data = sample();
+        let rle = compress(&data, Compression::Rle, 4);
+        assert!(rle.len() * 10 < data.len(), "rle was {} bytes", rle.len());
+        assert!(compress(&data, Compression::Deflate, 4).len() <= rle.len());
+    }
+
+    #[test]
+    fn test_rle_long_literal_and_repeat_runs() {
+        let mut data = Vec::new();
+        for i in 0..300u32 {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        data.extend(std::iter::repeat_n([1, 2, 3, 4], 500).flatten());
+        assert_eq!(rle_decode(&rle_encode(&data, 4), 4, data.len()).unwrap(), data);
+
+        // Unicode cells are 6 bytes wide
+        let wide: Vec<u8> = std::iter::repeat_n([0x88, 0x25, 0, 9, 9, 9], 40).flatten().collect();
+        assert_eq!(rle_decode(&rle_encode(&wide, 6), 6, wide.len()).unwrap(), wide);
+    }
+
+    #[test]
+    fn test_rle_rejects_truncated_input() {
+        assert!(rle_decode(&[0x85, 1, 2], 4, 1024).is_err());
+        assert!(rle_decode(&[0x01, 1, 2, 3, 4], 4, 1024).is_err());
+    }
+
+    #[test]
+    fn test_decompression_bomb_is_cut_off() {
+        // 1 MB of maximal runs would decode to 128 MB
+        let bomb: Vec<u8> = std::iter::repeat_n([0xff, 1, 2, 3, 4], 200_000).flatten().collect();
+        assert!(decompress(&bomb, Compression::Rle, 4, 4096).is_err());
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&bomb).unwrap();
+        let deflated = encoder.finish().unwrap();
+        assert!(deflated.len() < 10_000);
+        assert!(decompress(&deflated, Compression::Deflate, 4, 4096).is_err());
+    }
+}
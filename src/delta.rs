@@ -0,0 +1,139 @@
+use crate::message::{AsciiFrame, FrameDelta};
+
+/// Frames between forced keyframes (~3s at 30 fps)
+pub const KEYFRAME_INTERVAL: u32 = 90;
+
+/// Output of the delta encoder for a single captured frame
+#[derive(Debug, Clone)]
+pub enum EncodedFrame {
+    Key { seq: u32, frame: AsciiFrame },
+    Delta(FrameDelta),
+}
+
+/// Turns a stream of full frames into keyframes and deltas
+pub struct DeltaEncoder {
+    prev: Option<AsciiFrame>,
+    seq: u32,
+    since_keyframe: u32,
+    keyframe_interval: u32,
+    force_keyframe: bool,
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: u32) -> Self {
+        Self {
+            prev: None,
+            seq: 0,
+            since_keyframe: 0,
+            keyframe_interval,
+            force_keyframe: true,
+        }
+    }
+
+    /// Make the next encoded frame a keyframe
+    pub fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    /// Encode the next frame of the stream
+    pub fn encode(&mut self, frame: &AsciiFrame) -> EncodedFrame {
+        let base_seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+
+        let due = self.force_keyframe || self.since_keyframe + 1 >= self.keyframe_interval;
+        let delta = match self.prev.as_ref() {
+            Some(prev) if !due => FrameDelta::between(prev, frame, base_seq, self.seq),
+            _ => None,
+        };
+
+        self.prev = Some(frame.clone());
+
+        match delta {
+            // A delta touching most of the frame is bigger than the frame itself
+            Some(delta) if delta.changed_cells() * 2 < frame.cell_count() => {
+                self.since_keyframe += 1;
+                EncodedFrame::Delta(delta)
+            }
+            _ => {
+                self.force_keyframe = false;
+                self.since_keyframe = 0;
+                EncodedFrame::Key {
+                    seq: self.seq,
+                    frame: frame.clone(),
+                }
+            }
+        }
+    }
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self::new(KEYFRAME_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_with(cells: &[(u16, u16, char)]) -> AsciiFrame {
+        let mut frame = AsciiFrame::new(8, 4);
+        for &(x, y, ch) in cells {
+            frame.set_cell(x, y, ch, 200, 100, 50);
+        }
+        frame
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let a = frame_with(&[(0, 0, '#')]);
+        let b = frame_with(&[(1, 0, '@'), (3, 0, '.'), (7, 3, '%')]);
+
+        let delta = FrameDelta::between(&a, &b, 1, 2).unwrap();
+        // (0,0)..(3,0) merge into one run, (7,3) is separate
+        assert_eq!(delta.runs.len(), 2);
+
+        let mut rebuilt = a.clone();
+        delta.apply(&mut rebuilt).unwrap();
+        assert_eq!(rebuilt.data, b.data);
+    }
+
+    #[test]
+    fn test_delta_rejects_size_mismatch() {
+        let a = AsciiFrame::new(8, 4);
+        let b = AsciiFrame::new(4, 4);
+        assert!(FrameDelta::between(&a, &b, 0, 1).is_none());
+
+        let delta = FrameDelta::between(&a, &a, 0, 1).unwrap();
+        let mut other = AsciiFrame::new(4, 4);
+        assert!(delta.apply(&mut other).is_err());
+    }
+
+    #[test]
+    fn test_encoder_keyframe_schedule() {
+        let mut encoder = DeltaEncoder::new(3);
+        let frame = frame_with(&[(2, 2, 'x')]);
+
+        assert!(matches!(encoder.encode(&frame), EncodedFrame::Key { seq: 1, .. }));
+        assert!(matches!(encoder.encode(&frame), EncodedFrame::Delta(ref d) if d.base_seq == 1 && d.seq == 2));
+        assert!(matches!(encoder.encode(&frame), EncodedFrame::Delta(_)));
+        assert!(matches!(encoder.encode(&frame), EncodedFrame::Key { seq: 4, .. }));
+
+        encoder.force_keyframe();
+        assert!(matches!(encoder.encode(&frame), EncodedFrame::Key { seq: 5, .. }));
+    }
+
+    #[test]
+    fn test_encoder_falls_back_to_keyframe_on_big_change() {
+        let mut encoder = DeltaEncoder::new(100);
+        encoder.encode(&AsciiFrame::new(8, 4));
+
+        let mut busy = AsciiFrame::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                busy.set_cell(x, y, '#', 1, 2, 3);
+            }
+        }
+        assert!(matches!(encoder.encode(&busy), EncodedFrame::Key { .. }));
+    }
+}
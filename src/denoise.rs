@@ -0,0 +1,227 @@
+use crate::ascii::{ConvertOptions, Luma, RenderMode};
+use crate::image::RgbImage;
+use crate::message::{AsciiFrame, CellFormat};
+
+/// Weight of the previous color at full strength
+const MAX_SMOOTHING: f32 = 0.8;
+
+/// Brightness change a cell must make before its glyph may change, at full strength
+const MAX_MARGIN: f32 = 24.0;
+
+/// Channel change treated as movement rather than noise, shown at once
+const MOTION_THRESHOLD: f32 = 64.0;
+
+/// Parse a denoise strength given on the command line
+pub fn parse_strength(value: &str) -> anyhow::Result<f32> {
+    let strength: f32 = value.parse()?;
+    if !(strength.is_finite() && (0.0..=1.0).contains(&strength)) {
+        anyhow::bail!("Denoise strength must be between 0 and 1, not {}", value);
+    }
+    Ok(strength)
+}
+
+/// What a cell last showed
+struct HeldCell {
+    glyph: char,
+    /// Source brightness at which the glyph was last confirmed
+    luma: f32,
+    fg: [f32; 3],
+    bg: Option<[f32; 3]>,
+}
+
+/// Temporal filter against webcam sensor noise
+///
+/// Noise makes cells flicker between neighbouring glyphs even when nothing
+/// moves, and every flicker costs a changed cell in the delta stream. A cell
+/// keeps its glyph until the brightness of its source pixel has moved a margin
+/// away from where the glyph was chosen (hysteresis), and its colors are
+/// smoothed exponentially. Large changes are movement and pass straight
+/// through, so nothing trails.
+///
+/// Only `Ascii` glyphs are palette brightness levels. In the other modes a
+/// glyph is a dot pattern, outline or shape, so glyphs pass unchanged and only
+/// colors are smoothed.
+pub struct TemporalDenoise {
+    /// 0.0 leaves frames untouched, 1.0 is the strongest smoothing
+    strength: f32,
+    /// Whether glyphs are held back, which needs one source pixel per cell
+    hysteresis: bool,
+    /// Brightness measure the glyphs were picked by
+    luma: Luma,
+    cells: Vec<HeldCell>,
+    /// Size and format of the frames `cells` belongs to
+    shape: (u16, u16, CellFormat),
+}
+
+impl TemporalDenoise {
+    /// Denoiser for frames converted with `options`
+    pub fn new(strength: f32, options: &ConvertOptions) -> Self {
+        Self {
+            strength: strength.clamp(0.0, 1.0),
+            hysteresis: options.mode == RenderMode::Ascii,
+            luma: options.luma,
+            cells: Vec::new(),
+            shape: (0, 0, CellFormat::default()),
+        }
+    }
+
+    /// Forget the held cells, e.g. after the palette changed
+    pub fn reset(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Stabilize a freshly converted frame in place, given the image it was
+    /// converted from
+    pub fn apply(&mut self, frame: &mut AsciiFrame, image: &RgbImage) {
+        if self.strength == 0.0 {
+            return;
+        }
+
+        let shape = (frame.width, frame.height, frame.format);
+        if shape != self.shape {
+            self.shape = shape;
+            self.cells.clear();
+        }
+
+        let smoothing = self.strength * MAX_SMOOTHING;
+        let margin = self.strength * MAX_MARGIN;
+        let fresh = self.cells.is_empty();
+        let hysteresis =
+            self.hysteresis && (image.width, image.height) == (frame.width as usize, frame.height as usize);
+
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let Some((ch, r, g, b)) = frame.get_cell(x, y) else {
+                    continue;
+                };
+                let fg = [r as f32, g as f32, b as f32];
+                let bg = frame.get_background(x, y).map(|(r, g, b)| [r as f32, g as f32, b as f32]);
+                let luma = if hysteresis {
+                    let (r, g, b) = image.pixel(x as usize, y as usize);
+                    self.luma.of(r, g, b) as f32
+                } else {
+                    0.0
+                };
+
+                if fresh {
+                    self.cells.push(HeldCell { glyph: ch, luma, fg, bg });
+                    continue;
+                }
+
+                let held = &mut self.cells[y as usize * frame.width as usize + x as usize];
+                if ch == held.glyph || !hysteresis || (luma - held.luma).abs() > margin {
+                    held.glyph = ch;
+                    held.luma = luma;
+                }
+                smooth(&mut held.fg, fg, smoothing);
+                if let (Some(held_bg), Some(bg)) = (held.bg.as_mut(), bg) {
+                    smooth(held_bg, bg, smoothing);
+                }
+
+                let [r, g, b] = held.fg.map(|c| c.round() as u8);
+                frame.set_cell(x, y, held.glyph, r, g, b);
+                if let Some([r, g, b]) = held.bg.map(|bg| bg.map(|c| c.round() as u8)) {
+                    frame.set_background(x, y, r, g, b);
+                }
+            }
+        }
+    }
+}
+
+/// Exponential moving average of a color, jumping straight to large changes
+fn smooth(held: &mut [f32; 3], next: [f32; 3], smoothing: f32) {
+    let moving = held.iter().zip(next.iter()).any(|(a, b)| (a - b).abs() > MOTION_THRESHOLD);
+    for (h, n) in held.iter_mut().zip(next.iter()) {
+        *h = if moving { *n } else { *h * smoothing + n * (1.0 - smoothing) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(ch: char, v: u8) -> AsciiFrame {
+        let mut frame = AsciiFrame::new(1, 1);
+        frame.set_cell(0, 0, ch, v, v, v);
+        frame
+    }
+
+    /// Image of one pixel of brightness `v`
+    fn pixel(v: u8) -> RgbImage {
+        let mut image = RgbImage::new(1, 1);
+        image.set_pixel(0, 0, (v, v, v));
+        image
+    }
+
+    fn run(denoise: &mut TemporalDenoise, ch: char, v: u8) -> (char, u8, u8, u8) {
+        let mut frame = cell(ch, v);
+        denoise.apply(&mut frame, &pixel(v));
+        frame.get_cell(0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_noise_is_held_back() {
+        let mut denoise = TemporalDenoise::new(1.0, &ConvertOptions::default());
+        assert_eq!(run(&mut denoise, '+', 100), ('+', 100, 100, 100));
+
+        // Noise across a glyph boundary keeps the glyph, and the color barely moves
+        assert_eq!(run(&mut denoise, '*', 110).0, '+');
+        assert_eq!(run(&mut denoise, '+', 96).0, '+');
+        let (ch, r, _, _) = run(&mut denoise, '*', 110);
+        assert_eq!(ch, '+');
+        assert!((100..=104).contains(&r), "{}", r);
+    }
+
+    #[test]
+    fn test_real_changes_pass() {
+        let mut denoise = TemporalDenoise::new(1.0, &ConvertOptions::default());
+        run(&mut denoise, '+', 100);
+
+        // A lasting brightness change beyond the margin switches the glyph
+        assert_eq!(run(&mut denoise, '#', 130).0, '#');
+        // Movement shows at once instead of fading in
+        assert_eq!(run(&mut denoise, ' ', 10), (' ', 10, 10, 10));
+    }
+
+    #[test]
+    fn test_off_and_resized() {
+        let mut off = TemporalDenoise::new(0.0, &ConvertOptions::default());
+        run(&mut off, '+', 100);
+        assert_eq!(run(&mut off, '*', 110), ('*', 110, 110, 110));
+
+        // A frame of another size starts over instead of mixing cells
+        let mut denoise = TemporalDenoise::new(1.0, &ConvertOptions::default());
+        run(&mut denoise, '+', 100);
+        let mut wide = AsciiFrame::new(2, 1);
+        wide.set_cell(0, 0, '*', 110, 110, 110);
+        denoise.apply(&mut wide, &RgbImage::new(2, 1));
+        assert_eq!(wide.get_cell(0, 0), Some(('*', 110, 110, 110)));
+    }
+
+    #[test]
+    fn test_mono_braille_glyphs_change() {
+        // Mono braille paints every dot pattern white, so the foreground says
+        // nothing about the picture; patterns must still follow it
+        let options = ConvertOptions {
+            mode: RenderMode::Braille,
+            mono: true,
+            ..ConvertOptions::default()
+        };
+        let mut denoise = TemporalDenoise::new(1.0, &options);
+        let image = RgbImage::new(2, 4);
+        for ch in ['\u{2801}', '\u{2803}', '\u{2807}'] {
+            let mut frame = cell(ch, 255);
+            denoise.apply(&mut frame, &image);
+            assert_eq!(frame.get_cell(0, 0), Some((ch, 255, 255, 255)));
+        }
+    }
+
+    #[test]
+    fn test_parse_strength() {
+        assert_eq!(parse_strength("0.5").unwrap(), 0.5);
+        assert_eq!(parse_strength("1").unwrap(), 1.0);
+        for bad in ["-0.1", "1.5", "NaN", "inf", "strong"] {
+            assert!(parse_strength(bad).is_err(), "{}", bad);
+        }
+    }
+}
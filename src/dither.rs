@@ -0,0 +1,137 @@
+/// How luminance is reduced to the limited number of levels a palette offers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// Nearest level, flat areas band visibly on slow gradients
+    #[default]
+    None,
+    /// Error diffusion onto unvisited neighbours (weights 7/16, 3/16, 5/16, 1/16)
+    FloydSteinberg,
+    /// Ordered dithering with a 4x4 Bayer threshold matrix, stable between frames
+    Bayer,
+}
+
+impl std::str::FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(Self::None),
+            "floyd-steinberg" | "floyd" | "fs" => Ok(Self::FloydSteinberg),
+            "bayer" | "ordered" => Ok(Self::Bayer),
+            other => Err(anyhow::anyhow!("Unknown dithering: {}", other)),
+        }
+    }
+}
+
+/// Bayer threshold matrix, entries 0..16 spread evenly over each 4x4 tile
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantize a `width`-wide plane of luminance values (0.0..=255.0) to level
+/// indices in `0..levels`, level `i` standing for luminance `i * 255 / (levels - 1)`
+pub fn quantize(luma: &[f32], width: usize, levels: usize, dither: Dither) -> Vec<usize> {
+    let levels = levels.max(2);
+    let step = 255.0 / (levels - 1) as f32;
+    let level_of = |v: f32| (v / step).round().clamp(0.0, (levels - 1) as f32) as usize;
+
+    match dither {
+        Dither::None => luma.iter().map(|&v| level_of(v)).collect(),
+        Dither::Bayer => luma
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let t = BAYER_4X4[(i / width) % 4][(i % width) % 4] as f32;
+                level_of(v + ((t + 0.5) / 16.0 - 0.5) * step)
+            })
+            .collect(),
+        Dither::FloydSteinberg => {
+            let height = luma.len() / width.max(1);
+            let mut buf = luma.to_vec();
+            let mut out = vec![0; luma.len()];
+
+            for y in 0..height {
+                for x in 0..width {
+                    let i = y * width + x;
+                    let level = level_of(buf[i]);
+                    out[i] = level;
+
+                    let err = buf[i] - level as f32 * step;
+                    if x + 1 < width {
+                        buf[i + 1] += err * 7.0 / 16.0;
+                    }
+                    if y + 1 < height {
+                        if x > 0 {
+                            buf[i + width - 1] += err * 3.0 / 16.0;
+                        }
+                        buf[i + width] += err * 5.0 / 16.0;
+                        if x + 1 < width {
+                            buf[i + width + 1] += err / 16.0;
+                        }
+                    }
+                }
+            }
+
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 16;
+
+    /// Slow horizontal ramp spanning only a few palette levels, like skin tones
+    fn gradient() -> Vec<f32> {
+        (0..WIDTH * HEIGHT)
+            .map(|i| 60.0 + 8.0 * (i % WIDTH) as f32 / (WIDTH - 1) as f32)
+            .collect()
+    }
+
+    /// Mean distance between the output and the input ramp, averaged over
+    /// 4-pixel wide strips the way the eye blurs neighbouring cells
+    fn banding(levels: usize, dither: Dither) -> f32 {
+        let luma = gradient();
+        let out = quantize(&luma, WIDTH, levels, dither);
+        let step = 255.0 / (levels - 1) as f32;
+        let strips = WIDTH / 4;
+
+        let total: f32 = (0..strips)
+            .map(|s| {
+                let (mut shown, mut wanted) = (0.0, 0.0);
+                for y in 0..HEIGHT {
+                    for x in s * 4..s * 4 + 4 {
+                        shown += out[y * WIDTH + x] as f32 * step;
+                        wanted += luma[y * WIDTH + x];
+                    }
+                }
+                (shown - wanted).abs() / (4 * HEIGHT) as f32
+            })
+            .sum();
+        total / strips as f32
+    }
+
+    #[test]
+    fn test_dithering_reduces_banding() {
+        let plain = banding(68, Dither::None);
+        let fs = banding(68, Dither::FloydSteinberg);
+        let bayer = banding(68, Dither::Bayer);
+
+        assert!(fs * 2.0 < plain, "floyd-steinberg {} vs plain {}", fs, plain);
+        assert!(bayer * 2.0 < plain, "bayer {} vs plain {}", bayer, plain);
+    }
+
+    #[test]
+    fn test_flat_levels_stay_clean() {
+        // A value exactly on a level must not pick up dither noise
+        let step = 255.0 / 67.0;
+        let luma = vec![10.0 * step; WIDTH * HEIGHT];
+        for dither in [Dither::None, Dither::FloydSteinberg, Dither::Bayer] {
+            assert!(quantize(&luma, WIDTH, 68, dither).iter().all(|&l| l == 10), "{:?}", dither);
+        }
+
+        let extremes = [0.0, 255.0, 0.0, 255.0];
+        assert_eq!(quantize(&extremes, 2, 2, Dither::FloydSteinberg), vec![0, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,83 @@
+/// Horizontal and vertical luminance gradient of every pixel (Sobel operator)
+///
+/// Pixels outside the image repeat the nearest edge pixel, so borders do not
+/// register as edges. Both components are divided by 4, making a hard step
+/// from black to white a gradient of 255.
+pub fn sobel(luma: &[f32], width: usize, height: usize) -> Vec<(f32, f32)> {
+    let at = |x: isize, y: isize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        luma[y * width + x]
+    };
+
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x - 1, y) + at(x - 1, y + 1));
+            let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x, y - 1) + at(x + 1, y - 1));
+            out.push((gx / 4.0, gy / 4.0));
+        }
+    }
+    out
+}
+
+/// Glyph following the edge that runs across a pixel with gradient (gx, gy)
+///
+/// The edge is perpendicular to the gradient. Horizontal edges with the bright
+/// side on top, like the lower outline of a lit face, sit low as '_'.
+pub fn edge_glyph(gx: f32, gy: f32) -> char {
+    // Gradient angle with y pointing down, folded into 0..180
+    let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0);
+
+    match angle {
+        a if !(22.5..157.5).contains(&a) => '|',
+        a if a < 67.5 => '/',
+        a if a < 112.5 => {
+            if gy < 0.0 {
+                '_'
+            } else {
+                '-'
+            }
+        }
+        _ => '\\',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize, f: impl Fn(usize, usize) -> f32) -> Vec<f32> {
+        (0..width * height).map(|i| f(i % width, i / width)).collect()
+    }
+
+    #[test]
+    fn test_sobel_step() {
+        // Dark left half, bright right half
+        let luma = image(6, 3, |x, _| if x < 3 { 0.0 } else { 255.0 });
+        let grad = sobel(&luma, 6, 3);
+        let at = |x: usize, y: usize| grad[y * 6 + x];
+
+        assert_eq!(at(2, 1), (255.0, 0.0));
+        assert_eq!(at(3, 1), (255.0, 0.0));
+        assert_eq!(at(0, 1), (0.0, 0.0));
+        assert_eq!(at(5, 0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_edge_glyphs() {
+        let glyph_at = |luma: &[f32]| {
+            let (gx, gy) = sobel(luma, 5, 5)[2 * 5 + 2];
+            edge_glyph(gx, gy)
+        };
+
+        assert_eq!(glyph_at(&image(5, 5, |x, _| (x * 60) as f32)), '|');
+        assert_eq!(glyph_at(&image(5, 5, |_, y| (y * 60) as f32)), '-');
+        assert_eq!(glyph_at(&image(5, 5, |_, y| 255.0 - (y * 60) as f32)), '_');
+        // Bright towards the bottom right: the edge runs bottom left to top right
+        assert_eq!(glyph_at(&image(5, 5, |x, y| ((x + y) * 30) as f32)), '/');
+        assert_eq!(glyph_at(&image(5, 5, |x, y| ((x + 4 - y) * 30) as f32)), '\\');
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::TAU;
+use std::time::Instant;
+
+use ratatui::buffer::{Buffer, Cell};
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use tachyonfx::fx::{self, Glitch};
+use tachyonfx::{color_from_hsl, color_to_hsl, Duration, Effect, Interpolation, IntoEffect, Shader};
+
+use crate::message::AsciiFrame;
+
+/// How long a tile takes to fade in after its user joins, or out after they leave
+const FADE_MS: u32 = 800;
+
+/// Period of one color drift cycle
+const DRIFT_MS: u32 = 6000;
+/// Largest hue rotation of the color drift, in degrees
+const DRIFT_HUE: f32 = 120.0;
+
+/// Time the bright scanline takes to roll from top to bottom
+const SCANLINE_MS: u32 = 2500;
+/// Brightness kept on every other row
+const SCANLINE_DIM: f32 = 0.55;
+/// Brightness of the rolling line
+const SCANLINE_BRIGHT: f32 = 1.3;
+
+/// Animated effects that can be switched on per tile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectKind {
+    /// Cells randomly flicker to other glyphs
+    Glitch,
+    /// Hue slowly swings back and forth
+    ColorDrift,
+    /// Dimmed alternate rows with a rolling bright line, like a CRT
+    Scanlines,
+}
+
+impl EffectKind {
+    pub const ALL: [Self; 3] = [Self::Glitch, Self::ColorDrift, Self::Scanlines];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Glitch => "glitch",
+            Self::ColorDrift => "drift",
+            Self::Scanlines => "scanlines",
+        }
+    }
+
+    fn build(self) -> Effect {
+        match self {
+            Self::Glitch => Glitch::builder()
+                .cell_glitch_ratio(0.03)
+                .action_start_delay_ms(200..2500)
+                .action_ms(60..250)
+                .build()
+                .into_effect(),
+            Self::ColorDrift => fx::repeating(fx::effect_fn(
+                (),
+                (DRIFT_MS, Interpolation::Linear),
+                |_, ctx, cells| {
+                    // One smooth swing out and back per cycle
+                    let shift = DRIFT_HUE * (1.0 - (ctx.timer.alpha() * TAU).cos()) / 2.0;
+                    for (_, cell) in cells {
+                        map_colors(cell, |color| {
+                            let (h, s, l) = color_to_hsl(&color);
+                            color_from_hsl((h + shift) % 360.0, s, l)
+                        });
+                    }
+                },
+            )),
+            Self::Scanlines => fx::repeating(fx::effect_fn(
+                (),
+                (SCANLINE_MS, Interpolation::Linear),
+                |_, ctx, cells| {
+                    let rows = ctx.area.height.max(1);
+                    let bright = ctx.area.y + ((ctx.timer.alpha() * rows as f32) as u16).min(rows - 1);
+                    for (pos, cell) in cells {
+                        let level = if pos.y == bright {
+                            SCANLINE_BRIGHT
+                        } else if (pos.y - ctx.area.y) % 2 == 1 {
+                            SCANLINE_DIM
+                        } else {
+                            1.0
+                        };
+                        map_colors(cell, |color| scale(color, level));
+                    }
+                },
+            )),
+        }
+    }
+}
+
+/// Fade a tile's colors up from black, or down to black when `out` is set
+fn fade(out: bool) -> Effect {
+    fx::effect_fn((), (FADE_MS, Interpolation::QuadOut), move |_, ctx, cells| {
+        let alpha = ctx.timer.alpha();
+        let level = if out { 1.0 - alpha } else { alpha };
+        for (_, cell) in cells {
+            map_colors(cell, |color| scale(color, level));
+        }
+    })
+}
+
+/// Apply `f` to the true color foreground and background of a cell
+///
+/// Terminal default and indexed colors are left alone, so effects never paint
+/// over the user's terminal background.
+fn map_colors(cell: &mut Cell, f: impl Fn(Color) -> Color) {
+    if let Color::Rgb(..) = cell.fg {
+        cell.fg = f(cell.fg);
+    }
+    if let Color::Rgb(..) = cell.bg {
+        cell.bg = f(cell.bg);
+    }
+}
+
+fn scale(color: Color, level: f32) -> Color {
+    match color {
+        Color::Rgb(r, g, b) => {
+            let s = |c: u8| (c as f32 * level).round().clamp(0.0, 255.0) as u8;
+            Color::Rgb(s(r), s(g), s(b))
+        }
+        other => other,
+    }
+}
+
+/// Effects running on one user's tile
+#[derive(Default)]
+struct TileEffects {
+    toggled: Vec<(EffectKind, Effect)>,
+    /// Fade in after joining or out after leaving, dropped once finished
+    fade: Option<Effect>,
+}
+
+/// A user who left, kept on screen while their tile fades out
+struct Departure {
+    frame: AsciiFrame,
+    /// Time since they left, so the fade ends even if the tile is never drawn
+    age: Duration,
+}
+
+/// Animated effects of every video tile
+///
+/// Tiles fade in when a user's first frame arrives and fade out when their
+/// frames disappear. Toggled effects are kept per user, so they survive the
+/// tile moving around the layout.
+pub struct Effects {
+    tiles: HashMap<String, TileEffects>,
+    /// Last frame of users who left, drawn while their tile fades out
+    departing: HashMap<String, Departure>,
+    /// Users whose frames were seen at the previous draw
+    previous: HashSet<String>,
+    last_tick: Instant,
+    /// Time since the previous draw, by which every effect advances
+    elapsed: Duration,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Self {
+            tiles: HashMap::new(),
+            departing: HashMap::new(),
+            previous: HashSet::new(),
+            last_tick: Instant::now(),
+            elapsed: Duration::default(),
+        }
+    }
+}
+
+impl Effects {
+    /// Advance the clock, call once per drawn frame
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.elapsed = now.duration_since(self.last_tick).into();
+        self.last_tick = now;
+    }
+
+    /// Start fades for users whose frames appeared or vanished since the last
+    /// draw, and add departing users' last frames so their tiles can fade out
+    ///
+    /// `left` holds the last frame of users who left since the last draw; a
+    /// tile that vanished without one has nothing to fade and is dropped.
+    pub fn sync(
+        &mut self,
+        frames: &mut HashMap<String, AsciiFrame>,
+        mut left: HashMap<String, AsciiFrame>,
+    ) {
+        for departure in self.departing.values_mut() {
+            departure.age += self.elapsed;
+        }
+        for username in frames.keys() {
+            if !self.previous.contains(username) {
+                self.departing.remove(username);
+                self.tiles.entry(username.clone()).or_default().fade = Some(fade(false));
+            }
+        }
+        for username in self.previous.drain() {
+            if frames.contains_key(&username) {
+                continue;
+            }
+            match left.remove(&username) {
+                Some(frame) => {
+                    self.tiles.entry(username.clone()).or_default().fade = Some(fade(true));
+                    self.departing.insert(
+                        username,
+                        Departure {
+                            frame,
+                            age: Duration::default(),
+                        },
+                    );
+                }
+                None => {
+                    self.tiles.remove(&username);
+                }
+            }
+        }
+        self.previous = frames.keys().cloned().collect();
+
+        // Departed tiles that finished fading are forgotten, toggles included.
+        // Tiles outside the layout are never rendered, so their fade cannot be
+        // relied on to finish and the departure's own age ends it instead.
+        let finished: Vec<String> = self
+            .departing
+            .iter()
+            .filter(|(username, departure)| {
+                departure.age >= Duration::from_millis(FADE_MS)
+                    || self.tiles.get(*username).is_none_or(|tile| tile.fade.is_none())
+            })
+            .map(|(username, _)| username.clone())
+            .collect();
+        for username in finished {
+            self.departing.remove(&username);
+            self.tiles.remove(&username);
+        }
+        for (username, departure) in &self.departing {
+            frames.insert(username.clone(), departure.frame.clone());
+        }
+    }
+
+    /// Switch an effect on a user's tile on or off, returning whether it is now on
+    pub fn toggle(&mut self, username: &str, kind: EffectKind) -> bool {
+        let tile = self.tiles.entry(username.to_string()).or_default();
+        match tile.toggled.iter().position(|(k, _)| *k == kind) {
+            Some(idx) => {
+                tile.toggled.remove(idx);
+                false
+            }
+            None => {
+                tile.toggled.push((kind, kind.build()));
+                true
+            }
+        }
+    }
+
+    /// Effects switched on for a user's tile, in the order they were enabled
+    pub fn enabled(&self, username: &str) -> Vec<EffectKind> {
+        self.tiles
+            .get(username)
+            .map(|tile| tile.toggled.iter().map(|(kind, _)| *kind).collect())
+            .unwrap_or_default()
+    }
+
+    /// Run a user's effects over their rendered tile
+    pub fn render(&mut self, username: &str, buf: &mut Buffer, area: Rect) {
+        let Some(tile) = self.tiles.get_mut(username) else {
+            return;
+        };
+        for (_, effect) in &mut tile.toggled {
+            effect.process(self.elapsed, buf, area);
+        }
+        if let Some(fade) = tile.fade.as_mut() {
+            fade.process(self.elapsed, buf, area);
+            if fade.done() {
+                tile.fade = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(color: Color) -> Buffer {
+        let mut buf = Buffer::empty(Rect::new(0, 0, 4, 4));
+        for cell in buf.content.iter_mut() {
+            cell.set_char('#').set_fg(color);
+        }
+        buf
+    }
+
+    fn frames(users: &[&str]) -> HashMap<String, AsciiFrame> {
+        users
+            .iter()
+            .map(|u| (u.to_string(), AsciiFrame::new(4, 4)))
+            .collect()
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut effects = Effects::default();
+        assert!(effects.toggle("alice", EffectKind::Scanlines));
+        assert!(effects.toggle("alice", EffectKind::Glitch));
+        assert_eq!(effects.enabled("alice"), vec![EffectKind::Scanlines, EffectKind::Glitch]);
+        assert!(!effects.toggle("alice", EffectKind::Scanlines));
+        assert_eq!(effects.enabled("alice"), vec![EffectKind::Glitch]);
+        assert!(effects.enabled("bob").is_empty());
+    }
+
+    #[test]
+    fn test_scanlines_dim_alternate_rows() {
+        let mut effects = Effects::default();
+        effects.toggle("alice", EffectKind::Scanlines);
+        // Halfway through the sweep the bright line is on row 2
+        effects.elapsed = Duration::from_millis(SCANLINE_MS / 2);
+
+        let mut buf = tile(Color::Rgb(100, 100, 100));
+        let area = buf.area;
+        effects.render("alice", &mut buf, area);
+        assert_eq!(buf[(0, 0)].fg, Color::Rgb(100, 100, 100));
+        assert_eq!(buf[(0, 1)].fg, Color::Rgb(55, 55, 55));
+        assert_eq!(buf[(0, 2)].fg, Color::Rgb(130, 130, 130));
+        assert_eq!(buf[(0, 3)].fg, Color::Rgb(55, 55, 55));
+        // The default background is not painted over
+        assert_eq!(buf[(0, 1)].bg, Color::Reset);
+    }
+
+    #[test]
+    fn test_join_and_leave_fade() {
+        let mut effects = Effects::default();
+        let mut shown = frames(&["alice", "bob"]);
+        effects.sync(&mut shown, HashMap::new());
+
+        // Freshly joined tiles start out black
+        let mut buf = tile(Color::Rgb(200, 200, 200));
+        let area = buf.area;
+        effects.render("bob", &mut buf, area);
+        assert_eq!(buf[(1, 1)].fg, Color::Rgb(0, 0, 0));
+
+        // Bob leaves: their last frame is kept until the fade out is done
+        let mut shown = frames(&["alice"]);
+        effects.sync(&mut shown, frames(&["bob"]));
+        assert!(shown.contains_key("bob"));
+
+        effects.elapsed = Duration::from_millis(FADE_MS);
+        let mut buf = tile(Color::Rgb(200, 200, 200));
+        let area = buf.area;
+        effects.render("bob", &mut buf, area);
+        assert_eq!(buf[(1, 1)].fg, Color::Rgb(0, 0, 0));
+
+        let mut shown = frames(&["alice"]);
+        effects.sync(&mut shown, HashMap::new());
+        assert!(!shown.contains_key("bob"));
+    }
+
+    #[test]
+    fn test_leave_without_render() {
+        let mut effects = Effects::default();
+        let mut shown = frames(&["alice", "bob", "carol"]);
+        effects.sync(&mut shown, HashMap::new());
+
+        // Carol leaves while their tile is outside the layout and never rendered
+        let mut shown = frames(&["alice", "bob"]);
+        effects.sync(&mut shown, frames(&["carol"]));
+        assert!(shown.contains_key("carol"));
+
+        effects.elapsed = Duration::from_millis(FADE_MS);
+        let mut shown = frames(&["alice", "bob"]);
+        effects.sync(&mut shown, HashMap::new());
+        assert!(!shown.contains_key("carol"));
+        assert!(!effects.tiles.contains_key("carol"));
+
+        // Their stale frame does not come back when someone else leaves
+        effects.elapsed = Duration::default();
+        let mut shown = frames(&["alice"]);
+        effects.sync(&mut shown, frames(&["bob"]));
+        assert!(shown.contains_key("bob"));
+        assert!(!shown.contains_key("carol"));
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::OnceLock;
+
+use crate::image::RgbImage;
+
+/// Weight of the previous frame's tone curve, keeps exposure from pumping
+const SMOOTHING: f32 = 0.85;
+
+/// Share of pixels ignored at each end of the histogram when stretching
+const STRETCH_CLIP: f32 = 0.01;
+
+/// Narrowest input range stretched to full scale, so noise in a black frame
+/// is not blown up into a gray mess
+const MIN_STRETCH_RANGE: f32 = 48.0;
+
+/// Auto-exposure applied to captured images before they become cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Exposure {
+    #[default]
+    Off,
+    /// Stretch the darkest and brightest percent of the histogram to full range
+    Stretch,
+    /// Histogram equalization, every brightness gets an equal share of the output
+    Equalize,
+}
+
+impl std::str::FromStr for Exposure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(Self::Off),
+            "stretch" | "contrast" => Ok(Self::Stretch),
+            "equalize" | "histogram" => Ok(Self::Equalize),
+            other => Err(anyhow::anyhow!("Unknown exposure mode: {}", other)),
+        }
+    }
+}
+
+/// sRGB transfer function, 8-bit encoded value to linear light in 0.0..=1.0
+pub fn to_linear(v: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, out) in table.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *out = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        table
+    })[v as usize]
+}
+
+/// Inverse of `to_linear`, returning 0.0..=255.0
+fn to_encoded(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    c * 255.0
+}
+
+/// Luminance computed in linear light (Rec. 709 weights), re-encoded for display
+///
+/// Weighting gamma-encoded values directly makes saturated colors too dark;
+/// gray stays the same either way.
+pub fn linear_luminance(r: u8, g: u8, b: u8) -> u8 {
+    let y = 0.2126 * to_linear(r) + 0.7152 * to_linear(g) + 0.0722 * to_linear(b);
+    to_encoded(y).round() as u8
+}
+
+/// Per-frame auto-exposure with a temporally smoothed tone curve
+pub struct AutoExposure {
+    mode: Exposure,
+    /// Output brightness for each input brightness
+    curve: Option<[f32; 256]>,
+}
+
+impl AutoExposure {
+    pub fn new(mode: Exposure) -> Self {
+        Self { mode, curve: None }
+    }
+
+    /// Re-expose an image in place
+    pub fn apply(&mut self, image: &mut RgbImage) {
+        if self.mode == Exposure::Off || image.data.is_empty() {
+            return;
+        }
+
+        let mut histogram = [0u32; 256];
+        for p in image.data.chunks_exact(3) {
+            histogram[linear_luminance(p[0], p[1], p[2]) as usize] += 1;
+        }
+
+        let target = match self.mode {
+            Exposure::Off => return,
+            Exposure::Stretch => stretch_curve(&histogram),
+            Exposure::Equalize => equalize_curve(&histogram),
+        };
+
+        let curve = match self.curve.as_mut() {
+            Some(curve) => {
+                for (c, t) in curve.iter_mut().zip(target.iter()) {
+                    *c = *c * SMOOTHING + t * (1.0 - SMOOTHING);
+                }
+                curve
+            }
+            None => self.curve.insert(target),
+        };
+
+        for p in image.data.chunks_exact_mut(3) {
+            let y = linear_luminance(p[0], p[1], p[2]);
+            let out = curve[y as usize];
+
+            if y == 0 {
+                let gray = out.round() as u8;
+                p.copy_from_slice(&[gray, gray, gray]);
+                continue;
+            }
+
+            // Scale in linear light so that hue and saturation survive
+            let gain = to_linear(out.round() as u8) / to_linear(y);
+            for c in p.iter_mut() {
+                *c = to_encoded(to_linear(*c) * gain).round() as u8;
+            }
+        }
+    }
+}
+
+/// Linear map of the 1st..99th percentile onto the full range
+fn stretch_curve(histogram: &[u32; 256]) -> [f32; 256] {
+    let total: u32 = histogram.iter().sum();
+    let clip = (total as f32 * STRETCH_CLIP) as u32;
+
+    let mut low = 0;
+    let mut seen = 0;
+    for (i, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen > clip {
+            low = i;
+            break;
+        }
+    }
+
+    let mut high = 255;
+    seen = 0;
+    for (i, &count) in histogram.iter().enumerate().rev() {
+        seen += count;
+        if seen > clip {
+            high = i;
+            break;
+        }
+    }
+
+    let low = low as f32;
+    let range = (high as f32 - low).max(MIN_STRETCH_RANGE);
+
+    let mut curve = [0.0; 256];
+    for (i, out) in curve.iter_mut().enumerate() {
+        *out = ((i as f32 - low) / range * 255.0).clamp(0.0, 255.0);
+    }
+    curve
+}
+
+/// Cumulative histogram as tone curve
+fn equalize_curve(histogram: &[u32; 256]) -> [f32; 256] {
+    let total: u32 = histogram.iter().sum();
+    let first = histogram.iter().copied().find(|&c| c > 0).unwrap_or(0);
+    let span = total.saturating_sub(first).max(1) as f32;
+
+    let mut curve = [0.0; 256];
+    let mut cumulative = 0;
+    for (out, &count) in curve.iter_mut().zip(histogram.iter()) {
+        cumulative += count;
+        *out = (cumulative.saturating_sub(first) as f32 / span * 255.0).clamp(0.0, 255.0);
+    }
+    curve
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dim horizontal ramp, like an unlit room
+    fn dim_image() -> RgbImage {
+        let mut image = RgbImage::new(64, 8);
+        for y in 0..8 {
+            for x in 0..64 {
+                let v = 10 + x as u8 / 2;
+                image.set_pixel(x, y, (v, v, v / 2));
+            }
+        }
+        image
+    }
+
+    fn mean_luminance(image: &RgbImage) -> f32 {
+        let sum: u32 = image
+            .data
+            .chunks_exact(3)
+            .map(|p| linear_luminance(p[0], p[1], p[2]) as u32)
+            .sum();
+        sum as f32 / (image.width * image.height) as f32
+    }
+
+    #[test]
+    fn test_linear_luminance() {
+        assert_eq!(linear_luminance(0, 0, 0), 0);
+        assert_eq!(linear_luminance(255, 255, 255), 255);
+        assert_eq!(linear_luminance(128, 128, 128), 128);
+        // Weighting the encoded values gives only 150 and 29
+        assert!(linear_luminance(0, 255, 0) > 200);
+        assert!(linear_luminance(0, 0, 255) > 60);
+    }
+
+    #[test]
+    fn test_dim_frames_are_brightened() {
+        for mode in [Exposure::Stretch, Exposure::Equalize] {
+            let mut image = dim_image();
+            AutoExposure::new(mode).apply(&mut image);
+
+            let (before, after) = (mean_luminance(&dim_image()), mean_luminance(&image));
+            assert!(after > before * 2.5, "{:?} mean went from {} to {}", mode, before, after);
+            // Order is kept: the ramp still rises left to right
+            assert!(image.pixel(0, 0).0 < image.pixel(32, 0).0);
+            assert!(image.pixel(32, 0).0 < image.pixel(63, 0).0);
+            // Warm tint survives the gain
+            let (r, _, b) = image.pixel(50, 4);
+            assert!(r > b, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_off_and_flat_black_are_untouched() {
+        let mut image = dim_image();
+        AutoExposure::new(Exposure::Off).apply(&mut image);
+        assert_eq!(image, dim_image());
+
+        // Sensor noise in a dark frame must not become full gray
+        let mut black = RgbImage::new(8, 8);
+        black.set_pixel(3, 3, (2, 2, 2));
+        AutoExposure::new(Exposure::Stretch).apply(&mut black);
+        assert!(black.pixel(3, 3).0 < 64);
+    }
+
+    #[test]
+    fn test_exposure_is_smoothed_over_time() {
+        let mut exposure = AutoExposure::new(Exposure::Stretch);
+        let mut first = dim_image();
+        exposure.apply(&mut first);
+
+        // The scene suddenly lights up: the curve eases over instead of jumping
+        let mut bright = RgbImage::new(64, 8);
+        for x in 0..64 {
+            for y in 0..8 {
+                let v = 128 + x as u8 * 2;
+                bright.set_pixel(x, y, (v, v, v));
+            }
+        }
+        let mut eased = bright.clone();
+        exposure.apply(&mut eased);
+        let mut fresh = bright.clone();
+        AutoExposure::new(Exposure::Stretch).apply(&mut fresh);
+
+        assert_ne!(eased, fresh);
+        assert!(mean_luminance(&eased) > mean_luminance(&fresh));
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::ascii;
+use crate::image::RgbImage;
+use crate::message::AsciiFrame;
+
+/// A per-frame filter in the capture pipeline
+///
+/// `apply_rgb` runs on the scaled camera image before it is turned into cells,
+/// `apply_frame` on the finished frame before it is sent. Filters implement
+/// whichever stage they need; both do nothing by default.
+pub trait FrameFilter: Send {
+    fn apply_rgb(&mut self, _image: &mut RgbImage) {}
+
+    fn apply_frame(&mut self, _frame: &mut AsciiFrame) {}
+}
+
+/// Ordered list of filters, applied first to last
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn FrameFilter>>,
+}
+
+impl FilterChain {
+    /// Build the chain for a list of built-in filters
+    pub fn new(specs: &[FilterSpec]) -> Self {
+        Self {
+            filters: specs.iter().map(FilterSpec::build).collect(),
+        }
+    }
+
+    /// Add a filter after the others
+    pub fn push(&mut self, filter: Box<dyn FrameFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn apply_rgb(&mut self, image: &mut RgbImage) {
+        for filter in &mut self.filters {
+            filter.apply_rgb(image);
+        }
+    }
+
+    pub fn apply_frame(&mut self, frame: &mut AsciiFrame) {
+        for filter in &mut self.filters {
+            filter.apply_frame(frame);
+        }
+    }
+}
+
+/// Configuration of a built-in filter, written `name` or `name=value`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterSpec {
+    /// Add to every channel, -255..=255
+    Brightness(i32),
+    /// Scale the distance from mid gray, 1.0 keeps the image
+    Contrast(f32),
+    /// Like `Contrast`, but on the colors of the finished cells, leaving the
+    /// glyphs as converted
+    CellContrast(f32),
+    /// Scale the distance from the pixel's gray, 0.0 is grayscale
+    Saturation(f32),
+    Invert,
+    /// Swap left and right, like looking into a mirror
+    Mirror,
+    /// Swap top and bottom
+    Flip,
+    /// Rotate clockwise by 90, 180 or 270 degrees
+    Rotate(u16),
+}
+
+impl FilterSpec {
+    /// Parse a comma separated list such as `mirror,contrast=1.3`
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    pub fn build(&self) -> Box<dyn FrameFilter> {
+        match *self {
+            Self::Brightness(amount) => Box::new(Brightness(amount)),
+            Self::Contrast(factor) => Box::new(Contrast(factor)),
+            Self::CellContrast(factor) => Box::new(CellContrast(factor)),
+            Self::Saturation(factor) => Box::new(Saturation(factor)),
+            Self::Invert => Box::new(Invert),
+            Self::Mirror => Box::new(Mirror),
+            Self::Flip => Box::new(Flip),
+            Self::Rotate(degrees) => Box::new(Rotate(degrees)),
+        }
+    }
+}
+
+impl std::str::FromStr for FilterSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+        let value = |default| value.unwrap_or(default);
+
+        let spec = match name.to_ascii_lowercase().as_str() {
+            "brightness" => match value("0").parse()? {
+                amount @ -255..=255 => Self::Brightness(amount),
+                other => bail!("Brightness must be between -255 and 255, not {}", other),
+            },
+            "contrast" => Self::Contrast(parse_factor(name, value("1"))?),
+            "cell-contrast" => Self::CellContrast(parse_factor(name, value("1"))?),
+            "saturation" => Self::Saturation(parse_factor(name, value("1"))?),
+            "invert" => Self::Invert,
+            "mirror" => Self::Mirror,
+            "flip" => Self::Flip,
+            "rotate" => match value("180").parse()? {
+                degrees @ (90 | 180 | 270) => Self::Rotate(degrees),
+                other => bail!("Rotation must be 90, 180 or 270 degrees, not {}", other),
+            },
+            other => return Err(anyhow!("Unknown filter: {}", other)),
+        };
+        Ok(spec)
+    }
+}
+
+/// Parse the factor of a scaling filter, which must be a number from 0 up
+fn parse_factor(name: &str, value: &str) -> Result<f32> {
+    let factor: f32 = value.parse()?;
+    if !(factor.is_finite() && factor >= 0.0) {
+        bail!("The {} factor must be a number from 0 up, not {}", name, value);
+    }
+    Ok(factor)
+}
+
+/// Apply a function to every channel of every pixel
+fn map_channels(image: &mut RgbImage, f: impl Fn(f32) -> f32) {
+    for c in image.data.iter_mut() {
+        *c = f(*c as f32).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+struct Brightness(i32);
+
+impl FrameFilter for Brightness {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        let amount = self.0 as f32;
+        map_channels(image, |c| c + amount);
+    }
+}
+
+struct Contrast(f32);
+
+impl FrameFilter for Contrast {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        let factor = self.0;
+        map_channels(image, |c| (c - 128.0) * factor + 128.0);
+    }
+}
+
+struct CellContrast(f32);
+
+impl FrameFilter for CellContrast {
+    fn apply_frame(&mut self, frame: &mut AsciiFrame) {
+        ascii::adjust_contrast(frame, self.0, 0);
+    }
+}
+
+struct Saturation(f32);
+
+impl FrameFilter for Saturation {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        for p in image.data.chunks_exact_mut(3) {
+            let gray = 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+            for c in p.iter_mut() {
+                *c = (gray + (*c as f32 - gray) * self.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+struct Invert;
+
+impl FrameFilter for Invert {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        map_channels(image, |c| 255.0 - c);
+    }
+}
+
+struct Mirror;
+
+impl FrameFilter for Mirror {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        // Rows of no pixels cannot be chunked
+        if image.width == 0 {
+            return;
+        }
+        for row in image.data.chunks_exact_mut(image.width * 3) {
+            let width = row.len() / 3;
+            for x in 0..width / 2 {
+                for c in 0..3 {
+                    row.swap(x * 3 + c, (width - 1 - x) * 3 + c);
+                }
+            }
+        }
+    }
+}
+
+struct Flip;
+
+impl FrameFilter for Flip {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        let stride = image.width * 3;
+        for y in 0..image.height / 2 {
+            let (top, bottom) = image.data.split_at_mut((image.height - 1 - y) * stride);
+            top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+        }
+    }
+}
+
+/// Rotation that keeps the image size
+///
+/// Quarter turns stretch the rotated picture back over the original width and
+/// height, since the cell grid cannot change shape.
+struct Rotate(u16);
+
+impl FrameFilter for Rotate {
+    fn apply_rgb(&mut self, image: &mut RgbImage) {
+        if self.0 == 180 {
+            Mirror.apply_rgb(image);
+            Flip.apply_rgb(image);
+            return;
+        }
+
+        let (w, h) = (image.width, image.height);
+        let source = image.clone();
+        for y in 0..h {
+            for x in 0..w {
+                // Position in the output as a fraction, mapped back into the source
+                let u = (x as f32 + 0.5) / w as f32;
+                let v = (y as f32 + 0.5) / h as f32;
+                let (su, sv) = if self.0 == 90 { (v, 1.0 - u) } else { (1.0 - v, u) };
+                let sx = ((su * w as f32) as usize).min(w - 1);
+                let sy = ((sv * h as f32) as usize).min(h - 1);
+                image.set_pixel(x, y, source.pixel(sx, sy));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3x2 image with a distinct red value per pixel
+    fn sample() -> RgbImage {
+        let mut image = RgbImage::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                image.set_pixel(x, y, ((y * 3 + x) as u8 * 10, 100, 200));
+            }
+        }
+        image
+    }
+
+    fn reds(image: &RgbImage) -> Vec<u8> {
+        image.data.chunks_exact(3).map(|p| p[0]).collect()
+    }
+
+    fn run(list: &str, image: &mut RgbImage) {
+        FilterChain::new(&FilterSpec::parse_list(list).unwrap()).apply_rgb(image);
+    }
+
+    #[test]
+    fn test_geometry() {
+        let mut image = sample();
+        run("mirror", &mut image);
+        assert_eq!(reds(&image), vec![20, 10, 0, 50, 40, 30]);
+
+        let mut image = sample();
+        run("flip", &mut image);
+        assert_eq!(reds(&image), vec![30, 40, 50, 0, 10, 20]);
+
+        let mut image = sample();
+        run("rotate", &mut image);
+        assert_eq!(reds(&image), vec![50, 40, 30, 20, 10, 0]);
+
+        // A quarter turn one way and back is the identity on a square image
+        let mut square = RgbImage::new(4, 4);
+        square.set_pixel(0, 0, (255, 0, 0));
+        run("rotate=90", &mut square);
+        assert_eq!(square.pixel(3, 0), (255, 0, 0));
+        run("rotate=270", &mut square);
+        assert_eq!(square.pixel(0, 0), (255, 0, 0));
+    }
+
+    #[test]
+    fn test_color_adjustments() {
+        let mut image = sample();
+        run("brightness=-50,contrast=2", &mut image);
+        assert_eq!(image.pixel(0, 0), (0, 0, 172));
+
+        let mut image = sample();
+        run("invert", &mut image);
+        assert_eq!(image.pixel(1, 0), (245, 155, 55));
+
+        let mut image = sample();
+        run("saturation=0", &mut image);
+        let (r, g, b) = image.pixel(2, 1);
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn test_cell_contrast() {
+        let mut frame = AsciiFrame::new(2, 1);
+        frame.set_cell(0, 0, '+', 100, 128, 200);
+        frame.set_cell(1, 0, '#', 0, 0, 0);
+        let mut image = sample();
+        let mut chain = FilterChain::new(&FilterSpec::parse_list("cell-contrast=2").unwrap());
+        chain.apply_rgb(&mut image);
+        chain.apply_frame(&mut frame);
+
+        // Colors are stretched around mid gray, glyphs and the image are kept
+        assert_eq!(frame.get_cell(0, 0), Some(('+', 72, 128, 255)));
+        assert_eq!(frame.get_cell(1, 0), Some(('#', 0, 0, 0)));
+        assert_eq!(image, sample());
+    }
+
+    #[test]
+    fn test_empty_images() {
+        let all = "brightness=10,contrast=2,saturation=0,invert,mirror,flip,rotate,rotate=90";
+        for (width, height) in [(0, 0), (0, 3), (3, 0)] {
+            let mut image = RgbImage::new(width, height);
+            run(all, &mut image);
+            assert!(image.data.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_order_matters() {
+        let mut a = sample();
+        run("brightness=40,invert", &mut a);
+        let mut b = sample();
+        run("invert,brightness=40", &mut b);
+        assert_eq!(a.pixel(0, 0).0, 215);
+        assert_eq!(b.pixel(0, 0).0, 255);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(FilterSpec::parse_list("mirror, contrast=1.5").is_ok());
+        assert!(FilterSpec::parse_list("cell-contrast=1.5").is_ok());
+        assert!(FilterSpec::parse_list("blur").is_err());
+        assert!(FilterSpec::parse_list("rotate=45").is_err());
+        assert!(FilterSpec::parse_list("brightness=lots").is_err());
+        assert!(FilterSpec::parse_list("brightness=-255,brightness=255").is_ok());
+        assert!(FilterSpec::parse_list("brightness=100000").is_err());
+        assert!(FilterSpec::parse_list("brightness=-256").is_err());
+        assert!(FilterSpec::parse_list("saturation=0").is_ok());
+        for bad in ["NaN", "inf", "-1"] {
+            for name in ["contrast", "cell-contrast", "saturation"] {
+                let spec = format!("{}={}", name, bad);
+                assert!(FilterSpec::parse_list(&spec).is_err(), "{}", spec);
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::sync::OnceLock;
+
+/// Sub-pixel columns sampled per cell
+pub const MASK_WIDTH: usize = 3;
+/// Sub-pixel rows sampled per cell, terminal cells are roughly twice as tall as wide
+pub const MASK_HEIGHT: usize = 5;
+const MASK_LEN: usize = MASK_WIDTH * MASK_HEIGHT;
+
+/// How much a glyph's outline counts against matching its overall brightness
+///
+/// With plain squared error every binary mask scores the same on flat mid
+/// gray; weighting the shape term at half makes flat areas fall back to the
+/// glyph of matching density while structured areas still pick the outline.
+const SHAPE_WEIGHT: f32 = 0.5;
+
+/// 3x5 bitmap of every printable ASCII glyph, '#' where the glyph has ink
+const FONT: &[(char, [&str; MASK_HEIGHT])] = &[
+    (' ', ["...", "...", "...", "...", "..."]),
+    ('!', [".#.", ".#.", ".#.", "...", ".#."]),
+    ('"', ["#.#", "#.#", "...", "...", "..."]),
+    ('#', ["#.#", "###", "#.#", "###", "#.#"]),
+    ('$', [".##", "##.", ".#.", ".##", "##."]),
+    ('%', ["#..", "..#", ".#.", "#..", "..#"]),
+    ('&', [".#.", "#.#", ".#.", "#.#", ".##"]),
+    ('\'', [".#.", ".#.", "...", "...", "..."]),
+    ('(', [".#.", "#..", "#..", "#..", ".#."]),
+    (')', [".#.", "..#", "..#", "..#", ".#."]),
+    ('*', ["#.#", ".#.", "#.#", "...", "..."]),
+    ('+', ["...", ".#.", "###", ".#.", "..."]),
+    (',', ["...", "...", "...", ".#.", "#.."]),
+    ('-', ["...", "...", "###", "...", "..."]),
+    ('.', ["...", "...", "...", "...", ".#."]),
+    ('/', ["..#", "..#", ".#.", "#..", "#.."]),
+    ('0', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('1', [".#.", "##.", ".#.", ".#.", "###"]),
+    ('2', ["##.", "..#", ".#.", "#..", "###"]),
+    ('3', ["##.", "..#", ".#.", "..#", "##."]),
+    ('4', ["#.#", "#.#", "###", "..#", "..#"]),
+    ('5', ["###", "#..", "##.", "..#", "##."]),
+    ('6', [".##", "#..", "###", "#.#", "###"]),
+    ('7', ["###", "..#", ".#.", ".#.", ".#."]),
+    ('8', ["###", "#.#", "###", "#.#", "###"]),
+    ('9', ["###", "#.#", "###", "..#", "##."]),
+    (':', ["...", ".#.", "...", ".#.", "..."]),
+    (';', ["...", ".#.", "...", ".#.", "#.."]),
+    ('<', ["..#", ".#.", "#..", ".#.", "..#"]),
+    ('=', ["...", "###", "...", "###", "..."]),
+    ('>', ["#..", ".#.", "..#", ".#.", "#.."]),
+    ('?', ["##.", "..#", ".#.", "...", ".#."]),
+    ('@', ["###", "#.#", "###", "#..", "###"]),
+    ('A', [".#.", "#.#", "###", "#.#", "#.#"]),
+    ('B', ["##.", "#.#", "##.", "#.#", "##."]),
+    ('C', [".##", "#..", "#..", "#..", ".##"]),
+    ('D', ["##.", "#.#", "#.#", "#.#", "##."]),
+    ('E', ["###", "#..", "##.", "#..", "###"]),
+    ('F', ["###", "#..", "##.", "#..", "#.."]),
+    ('G', [".##", "#..", "#.#", "#.#", ".##"]),
+    ('H', ["#.#", "#.#", "###", "#.#", "#.#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..#", "..#", "..#", "#.#", "###"]),
+    ('K', ["#.#", "#.#", "##.", "#.#", "#.#"]),
+    ('L', ["#..", "#..", "#..", "#..", "###"]),
+    ('M', ["#.#", "###", "###", "#.#", "#.#"]),
+    ('N', ["##.", "#.#", "#.#", "#.#", "#.#"]),
+    ('O', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('P', ["##.", "#.#", "##.", "#..", "#.."]),
+    ('Q', [".#.", "#.#", "#.#", "##.", ".##"]),
+    ('R', ["##.", "#.#", "##.", "#.#", "#.#"]),
+    ('S', [".##", "#..", ".#.", "..#", "##."]),
+    ('T', ["###", ".#.", ".#.", ".#.", ".#."]),
+    ('U', ["#.#", "#.#", "#.#", "#.#", "###"]),
+    ('V', ["#.#", "#.#", "#.#", "#.#", ".#."]),
+    ('W', ["#.#", "#.#", "###", "###", "#.#"]),
+    ('X', ["#.#", "#.#", ".#.", "#.#", "#.#"]),
+    ('Y', ["#.#", "#.#", ".#.", ".#.", ".#."]),
+    ('Z', ["###", "..#", ".#.", "#..", "###"]),
+    ('[', ["##.", "#..", "#..", "#..", "##."]),
+    ('\\', ["#..", "#..", ".#.", "..#", "..#"]),
+    (']', [".##", "..#", "..#", "..#", ".##"]),
+    ('^', [".#.", "#.#", "...", "...", "..."]),
+    ('_', ["...", "...", "...", "...", "###"]),
+    ('`', ["#..", ".#.", "...", "...", "..."]),
+    ('a', ["...", "##.", ".##", "#.#", ".##"]),
+    ('b', ["#..", "#..", "##.", "#.#", "##."]),
+    ('c', ["...", "...", ".##", "#..", ".##"]),
+    ('d', ["..#", "..#", ".##", "#.#", ".##"]),
+    ('e', ["...", ".#.", "#.#", "##.", ".##"]),
+    ('f', ["..#", ".#.", "###", ".#.", ".#."]),
+    ('g', ["...", ".##", "#.#", ".##", "##."]),
+    ('h', ["#..", "#..", "##.", "#.#", "#.#"]),
+    ('i', [".#.", "...", ".#.", ".#.", ".#."]),
+    ('j', ["..#", "...", "..#", "#.#", ".#."]),
+    ('k', ["#..", "#.#", "##.", "##.", "#.#"]),
+    ('l', ["##.", ".#.", ".#.", ".#.", "###"]),
+    ('m', ["...", "...", "###", "###", "#.#"]),
+    ('n', ["...", "...", "##.", "#.#", "#.#"]),
+    ('o', ["...", "###", "#.#", "###", "..."]),
+    ('p', ["...", "##.", "#.#", "##.", "#.."]),
+    ('q', ["...", ".##", "#.#", ".##", "..#"]),
+    ('r', ["...", "...", ".##", "#..", "#.."]),
+    ('s', ["...", ".##", "#..", "..#", "##."]),
+    ('t', [".#.", "###", ".#.", ".#.", "..#"]),
+    ('u', ["...", "...", "#.#", "#.#", ".##"]),
+    ('v', ["...", "...", "#.#", "#.#", ".#."]),
+    ('w', ["...", "...", "#.#", "###", "###"]),
+    ('x', ["...", "#.#", ".#.", "#.#", "..."]),
+    ('y', ["...", "#.#", ".##", "..#", "##."]),
+    ('z', ["...", "###", ".#.", "#..", "###"]),
+    ('{', [".##", ".#.", "##.", ".#.", ".##"]),
+    ('|', [".#.", ".#.", ".#.", ".#.", ".#."]),
+    ('}', ["##.", ".#.", ".##", ".#.", "##."]),
+    ('~', ["...", "#..", "###", "..#", "..."]),
+];
+
+/// Glyphs the shape matcher picks from, chosen so that no two share a mask
+const SHAPE_GLYPHS: &str = " .,'`\"^-_=:|!/\\()[]<>v+xoLJT7YVUAHOB#MW8@";
+
+/// A glyph with its coverage mask, 1.0 for ink and 0.0 for background
+pub struct GlyphMask {
+    pub ch: char,
+    pub coverage: [f32; MASK_LEN],
+    /// Fraction of the cell covered by ink
+    pub density: f32,
+}
+
+/// Coverage masks of the glyphs used for shape matching, built once
+pub fn masks() -> &'static [GlyphMask] {
+    static MASKS: OnceLock<Vec<GlyphMask>> = OnceLock::new();
+    MASKS.get_or_init(|| SHAPE_GLYPHS.chars().filter_map(font_mask).collect())
+}
+
+/// Coverage mask of a printable ASCII glyph
+fn font_mask(ch: char) -> Option<GlyphMask> {
+    let (_, rows) = FONT.iter().find(|(c, _)| *c == ch)?;
+
+    let mut coverage = [0.0; MASK_LEN];
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.bytes().enumerate() {
+            coverage[y * MASK_WIDTH + x] = if c == b'#' { 1.0 } else { 0.0 };
+        }
+    }
+    let density = coverage.iter().sum::<f32>() / MASK_LEN as f32;
+    Some(GlyphMask { ch, coverage, density })
+}
+
+/// Fraction of a cell a glyph covers with ink, `None` when it cannot be measured
+///
+/// ASCII comes from the 3x5 font, shade and block elements from their nominal
+/// fill and braille from the number of raised dots.
+pub fn density(ch: char) -> Option<f32> {
+    match ch {
+        ' ' => Some(0.0),
+        '\u{2591}' => Some(0.25),
+        '\u{2592}' => Some(0.5),
+        '\u{2593}' => Some(0.75),
+        '\u{2588}' => Some(1.0),
+        // Half blocks
+        '\u{2580}' | '\u{2584}' | '\u{258C}' | '\u{2590}' => Some(0.5),
+        '\u{2800}'..='\u{28FF}' => Some((ch as u32 - 0x2800).count_ones() as f32 / 8.0),
+        _ => font_mask(ch).map(|mask| mask.density),
+    }
+}
+
+/// Pick the glyph whose mask best fits a block of sub-pixel brightness values
+/// (0.0 black to 1.0 white, row by row)
+pub fn best_match(block: &[f32; MASK_LEN]) -> &'static GlyphMask {
+    let mean = block.iter().sum::<f32>() / MASK_LEN as f32;
+
+    masks()
+        .iter()
+        .map(|mask| {
+            let shape: f32 = block
+                .iter()
+                .zip(mask.coverage.iter())
+                .map(|(v, m)| ((v - mean) - (m - mask.density)).powi(2))
+                .sum();
+            let density = (mean - mask.density).powi(2) * MASK_LEN as f32;
+            (SHAPE_WEIGHT * shape + density, mask)
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, mask)| mask)
+        .expect("glyph table is not empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks_are_distinct() {
+        let masks = masks();
+        for (i, a) in masks.iter().enumerate() {
+            for b in &masks[i + 1..] {
+                assert_ne!(a.coverage, b.coverage, "{:?} and {:?}", a.ch, b.ch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exact_shapes_match_themselves() {
+        for mask in masks() {
+            assert_eq!(best_match(&mask.coverage).ch, mask.ch);
+        }
+    }
+
+    #[test]
+    fn test_font_covers_printable_ascii() {
+        let chars: Vec<char> = FONT.iter().map(|(c, _)| *c).collect();
+        let ascii: Vec<char> = (' '..='~').collect();
+        assert_eq!(chars, ascii);
+        assert_eq!(masks().len(), SHAPE_GLYPHS.chars().count());
+    }
+
+    #[test]
+    fn test_density() {
+        assert_eq!(density(' '), Some(0.0));
+        assert!(density('.') < density(':'));
+        assert!(density(':') < density('#'));
+        assert!(density('\u{2591}') < density('\u{2593}'));
+        assert_eq!(density('\u{28FF}'), Some(1.0));
+        assert_eq!(density('\u{4E00}'), None);
+    }
+
+    #[test]
+    fn test_flat_blocks_match_density() {
+        assert_eq!(best_match(&[0.0; MASK_LEN]).ch, ' ');
+
+        // Flat mid gray picks a glyph of about half coverage, not an outline
+        let glyph = best_match(&[0.5; MASK_LEN]);
+        assert!((glyph.density - 0.5).abs() < 0.15, "{:?}", glyph.ch);
+    }
+}
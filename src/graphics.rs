@@ -0,0 +1,459 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use ratatui::layout::Rect;
+
+use crate::image::RgbImage;
+use crate::message::PixelFrame;
+
+/// How long to wait for the terminal to answer the capability query
+const QUERY_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Kitty graphics query for a 1x1 image, followed by a primary device
+/// attributes request that every terminal answers, so the reply has a known end
+const QUERY: &[u8] = b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c";
+
+/// Largest base64 payload of a single Kitty graphics escape
+const KITTY_CHUNK: usize = 4096;
+
+/// Levels per channel of the fixed Sixel palette
+const SIXEL_LEVELS: usize = 6;
+
+/// Cell size assumed when the terminal does not report its pixel size
+const DEFAULT_CELL_SIZE: (u16, u16) = (8, 16);
+
+/// Terminal protocols that draw real pixel images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Graphics {
+    Kitty,
+    Sixel,
+}
+
+/// How the client picks its tile renderer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GraphicsMode {
+    /// Glyphs only
+    #[default]
+    Off,
+    /// Ask the terminal, falling back to glyphs when it does not answer
+    Auto,
+    Kitty,
+    Sixel,
+}
+
+impl std::str::FromStr for GraphicsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" | "ascii" => Ok(Self::Off),
+            "auto" => Ok(Self::Auto),
+            "kitty" => Ok(Self::Kitty),
+            "sixel" => Ok(Self::Sixel),
+            other => Err(anyhow::anyhow!("Unknown graphics mode: {}", other)),
+        }
+    }
+}
+
+impl GraphicsMode {
+    /// Protocol to draw with, asking the terminal in `Auto` mode
+    pub fn resolve(self) -> Option<Graphics> {
+        match self {
+            Self::Off => None,
+            Self::Kitty => Some(Graphics::Kitty),
+            Self::Sixel => Some(Graphics::Sixel),
+            Self::Auto => {
+                let graphics = query();
+                match graphics {
+                    Some(graphics) => tracing::info!("Terminal supports {:?} graphics", graphics),
+                    None => tracing::info!("No pixel graphics support detected, drawing glyphs"),
+                }
+                graphics
+            }
+        }
+    }
+}
+
+/// Ask the terminal which graphics protocol it supports
+///
+/// Must run before the UI starts reading input. Terminals that do not answer
+/// within `QUERY_TIMEOUT` get `None`.
+#[cfg(unix)]
+pub fn query() -> Option<Graphics> {
+    use std::io::IsTerminal;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Instant;
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() || !io::stdout().is_terminal() {
+        return None;
+    }
+    let fd = stdin.as_raw_fd();
+
+    crossterm::terminal::enable_raw_mode().ok()?;
+    let reply = (|| {
+        let mut stdout = io::stdout();
+        stdout.write_all(QUERY).ok()?;
+        stdout.flush().ok()?;
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        let mut reply = Vec::new();
+        while device_attributes(&reply).is_none() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut poll = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `poll` points at one valid pollfd for the duration of the call
+            if left.is_zero() || unsafe { libc::poll(&mut poll, 1, left.as_millis() as i32) } <= 0 {
+                return None;
+            }
+            let mut buf = [0u8; 256];
+            // SAFETY: reads at most `buf.len()` bytes into `buf`
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+            if n <= 0 {
+                return None;
+            }
+            reply.extend_from_slice(&buf[..n as usize]);
+        }
+        Some(reply)
+    })();
+    let _ = crossterm::terminal::disable_raw_mode();
+
+    parse_reply(&reply?)
+}
+
+#[cfg(not(unix))]
+pub fn query() -> Option<Graphics> {
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parameters of the primary device attributes answer, `ESC [ ? 62 ; 4 c`
+fn device_attributes(reply: &[u8]) -> Option<Vec<&str>> {
+    let start = find(reply, b"\x1b[?")? + 3;
+    let end = start + reply[start..].iter().position(|&b| b == b'c')?;
+    let params = std::str::from_utf8(&reply[start..end]).ok()?;
+    Some(params.split(';').collect())
+}
+
+/// Protocol announced by the reply to `QUERY`
+///
+/// Kitty answers the graphics query itself; Sixel support is attribute 4 of
+/// the device attributes.
+fn parse_reply(reply: &[u8]) -> Option<Graphics> {
+    if find(reply, b"\x1b_Gi=31;OK").is_some() {
+        return Some(Graphics::Kitty);
+    }
+    if device_attributes(reply)?.contains(&"4") {
+        Some(Graphics::Sixel)
+    } else {
+        None
+    }
+}
+
+/// Pixel size of a terminal cell, from the terminal or a common default
+pub fn cell_size() -> (u16, u16) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            (size.width / size.columns, size.height / size.rows)
+        }
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Escape sequences drawing an image with the Kitty graphics protocol
+///
+/// The terminal scales the image over `cols` x `rows` cells at the cursor,
+/// replacing any earlier image with the same `id`. The cursor does not move.
+pub fn kitty_image(image: &RgbImage, id: u32, cols: u16, rows: u16) -> String {
+    let payload = base64(&image.data);
+    let chunks: Vec<&str> = payload
+        .as_bytes()
+        .chunks(KITTY_CHUNK)
+        .map(|c| std::str::from_utf8(c).expect("base64 is ASCII"))
+        .collect();
+
+    let mut out = kitty_delete(id);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            let _ = write!(
+                out,
+                "\x1b_Ga=T,f=24,s={},v={},i={},c={},r={},C=1,q=2,m={};",
+                image.width, image.height, id, cols, rows, more
+            );
+        } else {
+            let _ = write!(out, "\x1b_Gm={};", more);
+        }
+        out.push_str(chunk);
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+/// Remove a Kitty image and free its data
+pub fn kitty_delete(id: u32) -> String {
+    format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", id)
+}
+
+/// Sixel image, colors reduced to a fixed 6x6x6 palette
+pub fn sixel_image(image: &RgbImage) -> String {
+    let (width, height) = (image.width, image.height);
+    let colors = SIXEL_LEVELS.pow(3);
+    let max = SIXEL_LEVELS - 1;
+
+    // Pixel positions left at 0 stay transparent, raster attributes give the size
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for i in 0..colors {
+        let percent = |level: usize| level * 100 / max;
+        let n = SIXEL_LEVELS;
+        let (r, g, b) = (i / (n * n), i / n % n, i % n);
+        let _ = write!(out, "#{};2;{};{};{}", i, percent(r), percent(g), percent(b));
+    }
+
+    let level = |c: u8| (c as usize * max + 127) / 255;
+    let index: Vec<usize> = image
+        .data
+        .chunks_exact(3)
+        .map(|p| (level(p[0]) * SIXEL_LEVELS + level(p[1])) * SIXEL_LEVELS + level(p[2]))
+        .collect();
+
+    // One bit per pixel row of a six pixel band, per color and column
+    let mut masks = vec![0u8; colors * width];
+    for band in (0..height).step_by(6) {
+        masks.fill(0);
+        let mut used = vec![false; colors];
+        for dy in 0..6.min(height - band) {
+            for x in 0..width {
+                let c = index[(band + dy) * width + x];
+                masks[c * width + x] |= 1 << dy;
+                used[c] = true;
+            }
+        }
+
+        if band > 0 {
+            out.push('-');
+        }
+        let mut first = true;
+        for c in (0..colors).filter(|&c| used[c]) {
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            let _ = write!(out, "#{}", c);
+            push_sixels(&mut out, &masks[c * width..(c + 1) * width]);
+        }
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Append one color's sixels of a band, run-length encoded
+fn push_sixels(out: &mut String, masks: &[u8]) {
+    let mut i = 0;
+    while i < masks.len() {
+        let run = masks[i..].iter().take_while(|&&m| m == masks[i]).count();
+        let ch = (63 + masks[i]) as char;
+        if run >= 4 {
+            let _ = write!(out, "!{}{}", run, ch);
+        } else {
+            out.extend(std::iter::repeat_n(ch, run));
+        }
+        i += run;
+    }
+}
+
+/// Pixel images drawn over video tiles, written after the cells
+///
+/// Tiles that show images are left blank in the cell buffer. Images are only
+/// written again when their frame or place changed.
+pub struct ImageLayer {
+    graphics: Graphics,
+    cell_size: (u16, u16),
+    /// Images placed during the current draw, one per tile slot
+    pending: Vec<(Rect, PixelFrame)>,
+    /// What each slot showed after the last flush
+    drawn: Vec<(Rect, PixelFrame)>,
+}
+
+impl ImageLayer {
+    pub fn new(graphics: Graphics, cell_size: (u16, u16)) -> Self {
+        Self {
+            graphics,
+            cell_size,
+            pending: Vec::new(),
+            drawn: Vec::new(),
+        }
+    }
+
+    /// Queue an image to cover an area of cells
+    pub fn place(&mut self, area: Rect, frame: PixelFrame) {
+        self.pending.push((area, frame));
+    }
+
+    /// Write the images placed since the last flush
+    ///
+    /// Returns true when an image moved or went away and the screen must be
+    /// cleared: Sixel pixels stay until the cells under them are rewritten.
+    pub fn flush(&mut self, out: &mut impl Write) -> io::Result<bool> {
+        let pending = std::mem::take(&mut self.pending);
+
+        let mut moved = false;
+        let mut seq = String::new();
+        for (slot, (area, _)) in self.drawn.iter().enumerate() {
+            if pending.get(slot).is_none_or(|(a, _)| a != area) {
+                moved = true;
+                if self.graphics == Graphics::Kitty {
+                    seq.push_str(&kitty_delete(slot as u32 + 1));
+                }
+            }
+        }
+        if moved && self.graphics == Graphics::Sixel {
+            self.drawn.clear();
+            return Ok(true);
+        }
+
+        for (slot, (area, frame)) in pending.iter().enumerate() {
+            let unchanged = matches!(self.drawn.get(slot), Some((a, f)) if a == area && f == frame);
+            if unchanged || area.is_empty() {
+                continue;
+            }
+            let image = RgbImage::from_pixel_frame(frame);
+            // Save the cursor, draw at the top left of the area, restore it
+            let _ = write!(seq, "\x1b7\x1b[{};{}H", area.y + 1, area.x + 1);
+            match self.graphics {
+                Graphics::Kitty => seq.push_str(&kitty_image(&image, slot as u32 + 1, area.width, area.height)),
+                Graphics::Sixel => {
+                    let (w, h) = self.cell_size;
+                    let scaled = image.resize(area.width as usize * w as usize, area.height as usize * h as usize);
+                    seq.push_str(&sixel_image(&scaled));
+                }
+            }
+            seq.push_str("\x1b8");
+        }
+        self.drawn = pending;
+
+        if !seq.is_empty() {
+            out.write_all(seq.as_bytes())?;
+            out.flush()?;
+        }
+        Ok(false)
+    }
+
+    /// Remove every image, before leaving the UI
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.graphics == Graphics::Kitty {
+            for slot in 0..self.drawn.len() {
+                out.write_all(kitty_delete(slot as u32 + 1).as_bytes())?;
+            }
+            out.flush()?;
+        }
+        self.drawn.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PixelFormat;
+
+    fn solid(width: usize, height: usize, color: (u8, u8, u8)) -> RgbImage {
+        let mut image = RgbImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, color);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply(b"\x1b_Gi=31;OK\x1b\\\x1b[?62;22c"), Some(Graphics::Kitty));
+        assert_eq!(parse_reply(b"\x1b[?62;4;22c"), Some(Graphics::Sixel));
+        assert_eq!(parse_reply(b"\x1b[?1;2c"), None);
+        assert_eq!(parse_reply(b"\x1b[?62;4"), None);
+        assert!(device_attributes(b"\x1b[?62;4").is_none());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(&[0, 0, 0]), "AAAA");
+    }
+
+    #[test]
+    fn test_kitty_chunks() {
+        // 2000 pixels are 6000 bytes, 8000 base64 characters, two chunks
+        let seq = kitty_image(&solid(50, 40, (1, 2, 3)), 7, 20, 10);
+        assert!(seq.starts_with("\x1b_Ga=d,d=I,i=7,q=2\x1b\\"));
+        assert!(seq.contains("\x1b_Ga=T,f=24,s=50,v=40,i=7,c=20,r=10,C=1,q=2,m=1;"));
+        assert!(seq.contains("\x1b_Gm=0;"));
+        assert_eq!(seq.matches("\x1b\\").count(), 3);
+    }
+
+    #[test]
+    fn test_sixel() {
+        let seq = sixel_image(&solid(10, 7, (255, 0, 0)));
+        assert!(seq.starts_with("\x1bP0;1;0q\"1;1;10;7"));
+        assert!(seq.ends_with("\x1b\\"));
+        // Red is register 180; a full band of it, then one pixel row in the next
+        assert!(seq.contains("#180;2;100;0;0"));
+        assert!(seq.contains("#180!10~-#180!10@"));
+    }
+
+    #[test]
+    fn test_image_layer_redraws_only_changes() {
+        let frame = RgbImage::new(2, 2).to_pixel_frame(PixelFormat::Rgb);
+        let area = Rect::new(1, 1, 4, 2);
+        let mut layer = ImageLayer::new(Graphics::Kitty, (8, 16));
+        let mut out = Vec::new();
+
+        layer.place(area, frame.clone());
+        assert!(!layer.flush(&mut out).unwrap());
+        assert!(!out.is_empty());
+
+        out.clear();
+        layer.place(area, frame.clone());
+        layer.flush(&mut out).unwrap();
+        assert!(out.is_empty());
+
+        // A Kitty image that went away is deleted, Sixel asks for a clear
+        layer.flush(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), kitty_delete(1));
+
+        let mut layer = ImageLayer::new(Graphics::Sixel, (8, 16));
+        layer.place(area, frame.clone());
+        layer.flush(&mut Vec::new()).unwrap();
+        layer.place(Rect::new(2, 1, 4, 2), frame);
+        assert!(layer.flush(&mut Vec::new()).unwrap());
+    }
+}
@@ -0,0 +1,234 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use ffmpeg_next::codec;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::media::Type;
+use ffmpeg_next::software::scaling::{self, Flags};
+use ffmpeg_next::util::frame::Video;
+
+use crate::ascii::luminance;
+use crate::message::{PixelFormat, PixelFrame};
+
+/// Tightly packed RGB24 pixel buffer, 3 bytes per pixel without row padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl RgbImage {
+    /// Create a black image
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width * height * 3],
+        }
+    }
+
+    /// Copy an RGB24 video frame, dropping any row padding
+    pub fn from_video(frame: &Video) -> Self {
+        let width = frame.width() as usize;
+        let height = frame.height() as usize;
+        let stride = frame.stride(0);
+        let src = frame.data(0);
+
+        let mut image = Self::new(width, height);
+        for y in 0..height {
+            let row = &src[y * stride..];
+            let len = (width * 3).min(row.len());
+            image.data[y * width * 3..y * width * 3 + len].copy_from_slice(&row[..len]);
+        }
+        image
+    }
+
+    /// Decode the first frame of an image (or video) file with FFmpeg
+    pub fn load(path: &Path) -> Result<Self> {
+        ffmpeg_next::init()?;
+        let mut ictx = ffmpeg_next::format::input(path)
+            .with_context(|| format!("Failed to open image {}", path.display()))?;
+        let stream = ictx
+            .streams()
+            .best(Type::Video)
+            .ok_or_else(|| anyhow!("No image found in {}", path.display()))?;
+        let index = stream.index();
+        let mut decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()
+            .context("Failed to get image decoder")?;
+
+        let mut decoded = Video::empty();
+        let mut found = false;
+        for (stream, packet) in ictx.packets() {
+            if stream.index() == index
+                && decoder.send_packet(&packet).is_ok()
+                && decoder.receive_frame(&mut decoded).is_ok()
+            {
+                found = true;
+                break;
+            }
+        }
+        // Some decoders only hand out a frame once they are drained
+        if !found {
+            decoder.send_eof()?;
+            found = decoder.receive_frame(&mut decoded).is_ok();
+        }
+        if !found {
+            return Err(anyhow!("Could not decode an image from {}", path.display()));
+        }
+
+        let (width, height) = (decoded.width(), decoded.height());
+        let mut rgb = Video::new(Pixel::RGB24, width, height);
+        scaling::Context::get(decoded.format(), width, height, Pixel::RGB24, width, height, Flags::BILINEAR)?
+            .run(&decoded, &mut rgb)?;
+        Ok(Self::from_video(&rgb))
+    }
+
+    /// Get the pixel at (x, y), black when out of bounds
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        if x >= self.width || y >= self.height {
+            return (0, 0, 0);
+        }
+        let idx = (y * self.width + x) * 3;
+        (self.data[idx], self.data[idx + 1], self.data[idx + 2])
+    }
+
+    /// Copy of the image resampled to another size, bilinear
+    pub fn resize(&self, width: usize, height: usize) -> Self {
+        let mut out = Self::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return out;
+        }
+
+        // Source coordinate of the center of an output pixel
+        let map = |i: usize, out_len: usize, in_len: usize| {
+            let pos = ((i as f32 + 0.5) * in_len as f32 / out_len as f32 - 0.5).max(0.0);
+            let low = (pos as usize).min(in_len - 1);
+            (low, (low + 1).min(in_len - 1), pos - low as f32)
+        };
+
+        for y in 0..height {
+            let (y0, y1, fy) = map(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1, fx) = map(x, width, self.width);
+                let idx = (y * width + x) * 3;
+                for c in 0..3 {
+                    let at = |x: usize, y: usize| self.data[(y * self.width + x) * 3 + c] as f32;
+                    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+                    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+                    out.data[idx + c] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+                }
+            }
+        }
+        out
+    }
+
+    /// Copy of the image centered on a black canvas of another size, cut off
+    /// where it is larger
+    pub fn centered(&self, width: usize, height: usize) -> Self {
+        let mut out = Self::new(width, height);
+        // Offsets of the canvas in the image (positive) or the image in the canvas
+        let dx = self.width as isize - width as isize;
+        let dy = self.height as isize - height as isize;
+        for y in 0..height {
+            let sy = y as isize + dy / 2;
+            if sy < 0 || sy >= self.height as isize {
+                continue;
+            }
+            for x in 0..width {
+                let sx = x as isize + dx / 2;
+                if sx >= 0 && sx < self.width as isize {
+                    out.set_pixel(x, y, self.pixel(sx as usize, sy as usize));
+                }
+            }
+        }
+        out
+    }
+
+    /// Pixel frame carrying a copy of the image, or only its luminance
+    pub fn to_pixel_frame(&self, format: PixelFormat) -> PixelFrame {
+        let data = match format {
+            PixelFormat::Rgb => self.data.clone(),
+            PixelFormat::Luma => self
+                .data
+                .chunks_exact(3)
+                .map(|p| luminance(p[0], p[1], p[2]))
+                .collect(),
+        };
+        PixelFrame {
+            width: self.width as u16,
+            height: self.height as u16,
+            data,
+            format,
+        }
+    }
+
+    /// Image of a received pixel frame, luma frames becoming gray
+    pub fn from_pixel_frame(frame: &PixelFrame) -> Self {
+        let mut image = Self::new(frame.width as usize, frame.height as usize);
+        match frame.format {
+            PixelFormat::Rgb => {
+                let len = image.data.len().min(frame.data.len());
+                image.data[..len].copy_from_slice(&frame.data[..len]);
+            }
+            PixelFormat::Luma => {
+                for (p, &v) in image.data.chunks_exact_mut(3).zip(frame.data.iter()) {
+                    p.copy_from_slice(&[v, v, v]);
+                }
+            }
+        }
+        image
+    }
+
+    /// Set the pixel at (x, y), ignored when out of bounds
+    pub fn set_pixel(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let idx = (y * self.width + x) * 3;
+        self.data[idx] = r;
+        self.data[idx + 1] = g;
+        self.data[idx + 2] = b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize() {
+        let mut image = RgbImage::new(2, 1);
+        image.set_pixel(1, 0, (200, 100, 0));
+
+        let wide = image.resize(4, 2);
+        assert_eq!(wide.pixel(0, 0), (0, 0, 0));
+        assert_eq!(wide.pixel(3, 1), (200, 100, 0));
+        // Inner pixels blend their neighbours
+        assert_eq!(wide.pixel(1, 0), (50, 25, 0));
+
+        assert_eq!(RgbImage::from_pixel_frame(&wide.to_pixel_frame(PixelFormat::Rgb)), wide);
+        let gray = RgbImage::from_pixel_frame(&wide.to_pixel_frame(PixelFormat::Luma));
+        assert_eq!(gray.pixel(3, 1), (118, 118, 118));
+        assert_eq!(image.resize(2, 1), image);
+    }
+
+    #[test]
+    fn test_centered() {
+        let mut image = RgbImage::new(4, 1);
+        for x in 0..4 {
+            image.set_pixel(x, 0, (x as u8 + 1, 0, 0));
+        }
+
+        let cropped = image.centered(2, 1);
+        assert_eq!(cropped.pixel(0, 0), (2, 0, 0));
+        assert_eq!(cropped.pixel(1, 0), (3, 0, 0));
+
+        let boxed = image.centered(4, 3);
+        assert_eq!(boxed.pixel(0, 0), (0, 0, 0));
+        assert_eq!(boxed.pixel(3, 1), (4, 0, 0));
+        assert_eq!(boxed.pixel(3, 2), (0, 0, 0));
+    }
+}
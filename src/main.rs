@@ -1,12 +1,38 @@
 mod ascii;
+mod aspect;
+mod avatar;
+mod chroma;
 mod client;
+mod color;
+mod compress;
+mod delta;
+mod denoise;
+mod dither;
+mod edge;
+mod effects;
+mod exposure;
+mod filter;
+mod glyphs;
+mod graphics;
+mod image;
 mod message;
+mod palette;
+mod pattern;
+mod pipeline;
+mod privacy;
+#[cfg(unix)]
+mod pty;
 mod server;
+mod source;
 mod user;
+mod vt;
 mod webcam;
 mod ui;
+mod wire;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use ascii::RenderMode;
+use dither::Dither;
 use std::sync::Arc;
 
 #[tokio::main]
@@ -17,7 +43,32 @@ async fn main() -> Result<()> {
         .init();
 
     // Parse command line arguments
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let encoding = take_flag(&mut args, "--encoding");
+    let compression = take_flag(&mut args, "--compress");
+    let mode = take_flag(&mut args, "--mode");
+    let threshold = take_flag(&mut args, "--threshold");
+    let dither = take_flag(&mut args, "--dither");
+    let edge_threshold = take_flag(&mut args, "--edge-threshold");
+    let luma = take_flag(&mut args, "--luma");
+    let palette = take_flag(&mut args, "--palette");
+    let exposure = take_flag(&mut args, "--exposure");
+    let denoise = take_flag(&mut args, "--denoise");
+    let chroma_key = take_flag(&mut args, "--chroma-key");
+    let key_tolerance = take_flag(&mut args, "--key-tolerance");
+    let key_background = take_flag(&mut args, "--key-background");
+    let privacy = take_flag(&mut args, "--privacy");
+    let fit = take_flag(&mut args, "--fit");
+    let cell_aspect = take_flag(&mut args, "--cell-aspect");
+    let source = take_flag(&mut args, "--source");
+    let looping = take_switch(&mut args, "--loop");
+    let start = take_flag(&mut args, "--start");
+    let speed = take_flag(&mut args, "--speed");
+    let filters = take_flag(&mut args, "--filter");
+    let colors = take_flag(&mut args, "--colors");
+    let graphics = take_flag(&mut args, "--graphics");
+    let render = take_flag(&mut args, "--render");
+    let mono = take_switch(&mut args, "--mono");
 
     if args.len() > 1 && args[1] == "client" {
         // Client mode
@@ -35,6 +86,101 @@ async fn main() -> Result<()> {
 
         println!("Starting TermIO client as '{}' connecting to {}", username, server_url);
         let mut client = client::TermIOClient::new(username, server_url.clone());
+        if let Some(source) = source {
+            client.source = source.parse()?;
+        }
+        match &mut client.source {
+            source::SourceSpec::File(playback) => {
+                playback.looping = looping;
+                if let Some(start) = start {
+                    playback.start = start.parse()?;
+                }
+                if let Some(speed) = speed {
+                    playback.speed = speed.parse()?;
+                }
+            }
+            _ if looping || start.is_some() || speed.is_some() => {
+                bail!("--loop, --start and --speed need a file: source");
+            }
+            _ => {}
+        }
+        if let Some(encoding) = encoding {
+            client.encoding = encoding.parse()?;
+        }
+        if let Some(compression) = compression {
+            client.compression = compression.parse()?;
+        }
+        if let Some(exposure) = exposure {
+            client.webcam_config.exposure = exposure.parse()?;
+        }
+        if let Some(denoise) = denoise {
+            client.webcam_config.denoise = denoise::parse_strength(&denoise)?;
+        }
+        if let Some(color) = chroma_key {
+            let mut key = chroma::ChromaKey {
+                color: chroma::parse_color(&color)?,
+                ..Default::default()
+            };
+            if let Some(tolerance) = key_tolerance {
+                key.tolerance = chroma::parse_tolerance(&tolerance)?;
+            }
+            if let Some(background) = key_background {
+                key.background = background.parse()?;
+            }
+            client.webcam_config.chroma_key = Some(key);
+        } else if key_tolerance.is_some() || key_background.is_some() {
+            bail!("--key-tolerance and --key-background need --chroma-key");
+        }
+        if let Some(privacy) = privacy {
+            client.webcam_config.privacy = privacy.parse()?;
+        }
+        if let Some(fit) = fit {
+            client.webcam_config.fit = fit.parse()?;
+        }
+        client.webcam_config.cell_aspect = match cell_aspect.as_deref() {
+            None | Some("auto") => aspect::detect_cell_aspect(),
+            Some(value) => aspect::parse_cell_aspect(value)?,
+        };
+        if let Some(filters) = filters {
+            client.webcam_config.filters = filter::FilterSpec::parse_list(&filters)?;
+        }
+        let convert = &mut client.webcam_config.convert;
+        if let Some(mode) = mode {
+            convert.mode = mode.parse()?;
+        }
+        if let Some(threshold) = threshold {
+            convert.threshold = threshold.parse()?;
+        }
+        if let Some(edge_threshold) = edge_threshold {
+            convert.edge_threshold = edge_threshold.parse()?;
+        }
+        if let Some(luma) = luma {
+            convert.luma = luma.parse()?;
+        }
+        if let Some(palette) = palette {
+            convert.palette = palette::Palette::from_spec(&palette)?;
+        }
+        convert.dither = match dither {
+            Some(dither) => dither.parse()?,
+            // Braille has only two levels per dot and loses all shading without dithering
+            None if convert.mode == RenderMode::Braille => Dither::FloydSteinberg,
+            None => Dither::None,
+        };
+        convert.mono = mono;
+
+        // Ask the terminal before the UI takes over input, so viewers without
+        // pixel graphics are never sent pixel frames
+        let graphics = match graphics {
+            Some(mode) => mode.parse::<graphics::GraphicsMode>()?.resolve(),
+            None => None,
+        };
+        if let Some(render) = render {
+            client.render = Some(render.parse::<ascii::LocalRender>()?);
+        }
+        client.pixels = match (&graphics, client.render) {
+            (Some(_), _) => Some(message::PixelFormat::Rgb),
+            (None, render) => render.map(|r| r.pixel_format()),
+        };
         client.connect().await?;
 
         // Give the client a moment to connect
@@ -42,7 +188,11 @@ async fn main() -> Result<()> {
 
         // Start the terminal UI
         println!("Starting terminal UI...");
-        let mut ui = ui::TermioUI::new(client);
+        let color_depth = match colors.as_deref() {
+            None | Some("auto") => color::ColorDepth::detect(),
+            Some(depth) => depth.parse()?,
+        };
+        let mut ui = ui::TermioUI::new(client, color_depth, graphics);
         ui.run().await?;
 
         println!("Shutting down...");
@@ -63,3 +213,27 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Remove a `--flag` switch from the arguments and return whether it was present
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Remove a `--flag value` pair from the arguments and return its value
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let pos = args.iter().position(|a| a == name)?;
+    if pos + 1 < args.len() {
+        let value = args.remove(pos + 1);
+        args.remove(pos);
+        Some(value)
+    } else {
+        args.remove(pos);
+        None
+    }
+}
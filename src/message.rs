@@ -1,3 +1,4 @@
+use crate::compress::Compression;
 use serde::{Deserialize, Serialize};
 
 /// WebSocket message types for TermIO protocol
@@ -7,13 +8,64 @@ pub enum Message {
     /// Client joins the server with a username
     Join {
         username: String,
+        /// Encoding the client wants to receive (older clients omit this)
+        #[serde(default)]
+        encoding: Encoding,
+        /// Whether the client understands `FrameDelta`
+        #[serde(default)]
+        deltas: bool,
+        /// Compression of frame cell data, binary encoding only
+        #[serde(default)]
+        compression: Compression,
+        /// Whether the client draws pixel graphics or renders cells itself and
+        /// wants `PixelFrame`s
+        #[serde(default)]
+        pixels: bool,
+        /// Pixel layout the client needs, luma being enough for monochrome
+        #[serde(default)]
+        pixel_format: PixelFormat,
+        /// Richest cell layout the client can read; older clients omit this
+        /// and only read ASCII cells without backgrounds
+        #[serde(default)]
+        cell_format: CellFormat,
     },
 
-    /// Frame of ASCII video data from a user
+    /// Frame of ASCII video data from a user (a keyframe when deltas are in use)
     Frame {
         user_id: String,
         username: String,
         frame: AsciiFrame,
+        /// Sequence number that following deltas are based on
+        #[serde(default)]
+        seq: u32,
+    },
+
+    /// Cells that changed since the frame numbered `delta.base_seq`
+    FrameDelta {
+        user_id: String,
+        username: String,
+        delta: FrameDelta,
+    },
+
+    /// Downscaled image of a user's video, for viewers that draw pixels or
+    /// pick their own renderer
+    PixelFrame {
+        user_id: String,
+        username: String,
+        frame: PixelFrame,
+    },
+
+    /// Whether any viewer wants pixel frames, so senders only capture them when needed
+    PixelDemand {
+        wanted: bool,
+        /// Richest pixel layout any viewer needs
+        #[serde(default)]
+        format: PixelFormat,
+    },
+
+    /// Ask for a full frame of a user, or of every user when `user_id` is empty
+    KeyframeRequest {
+        user_id: String,
     },
 
     /// Chat message from a user
@@ -51,50 +103,367 @@ pub enum Message {
     Pong,
 }
 
+impl Message {
+    /// Bytes of cell data carried by a frame or delta, for bandwidth accounting
+    pub fn cell_bytes(&self) -> Option<usize> {
+        match self {
+            Message::Frame { frame, .. } => Some(frame.data.len()),
+            Message::FrameDelta { delta, .. } => Some(delta.runs.iter().map(|r| r.data.len()).sum()),
+            Message::PixelFrame { frame, .. } => Some(frame.data.len()),
+            _ => None,
+        }
+    }
+}
+
+/// Wire encoding negotiated per connection at join time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    /// Every message is a JSON text message
+    #[default]
+    Json,
+    /// Every message uses the compact binary framing in `wire`
+    Binary,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "binary" | "bin" => Ok(Self::Binary),
+            other => Err(anyhow::anyhow!("Unknown encoding: {}", other)),
+        }
+    }
+}
+
+/// Layout of a single cell in `AsciiFrame::data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CellFormat {
+    /// Glyphs are 3-byte Unicode scalars instead of a single ASCII byte
+    #[serde(default)]
+    pub unicode: bool,
+    /// Cells carry a background color after the foreground color
+    #[serde(default)]
+    pub background: bool,
+}
+
+impl CellFormat {
+    /// Bytes per cell
+    pub fn cell_size(self) -> usize {
+        self.glyph_size() + if self.background { 6 } else { 3 }
+    }
+
+    fn glyph_size(self) -> usize {
+        if self.unicode {
+            3
+        } else {
+            1
+        }
+    }
+
+    /// Bit flags used by the binary wire format
+    pub fn flags(self) -> u8 {
+        self.unicode as u8 | (self.background as u8) << 1
+    }
+
+    /// Whether a client reading up to this layout can read `other`
+    pub fn reads(self, other: CellFormat) -> bool {
+        (self.unicode || !other.unicode) && (self.background || !other.background)
+    }
+
+    /// This layout without the features a client reading up to `supported` lacks
+    pub fn limited_to(self, supported: CellFormat) -> Self {
+        Self {
+            unicode: self.unicode && supported.unicode,
+            background: self.background && supported.background,
+        }
+    }
+
+    pub fn from_flags(flags: u8) -> anyhow::Result<Self> {
+        if flags & !0x03 != 0 {
+            anyhow::bail!("Unsupported cell format flags: {:#04x}", flags);
+        }
+        Ok(Self {
+            unicode: flags & 0x01 != 0,
+            background: flags & 0x02 != 0,
+        })
+    }
+}
+
+/// Layout of a pixel in `PixelFrame::data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// Brightness only, 1 byte per pixel
+    Luma,
+    /// RGB24, 3 bytes per pixel
+    #[default]
+    Rgb,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Luma => 1,
+            Self::Rgb => 3,
+        }
+    }
+
+    /// Id used by the binary wire format
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Luma => 0,
+            Self::Rgb => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> anyhow::Result<Self> {
+        match id {
+            0 => Ok(Self::Luma),
+            1 => Ok(Self::Rgb),
+            other => Err(anyhow::anyhow!("Unknown pixel format id: {}", other)),
+        }
+    }
+}
+
+/// Video image sent alongside cell frames to viewers that draw pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PixelFrame {
+    pub width: u16,
+    pub height: u16,
+    /// Pixels in row-major order, `format.bytes_per_pixel()` bytes each
+    pub data: Vec<u8>,
+    #[serde(default)]
+    pub format: PixelFormat,
+}
+
 /// ASCII video frame data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AsciiFrame {
     pub width: u16,
     pub height: u16,
-    /// Compressed frame: stores (char, r, g, b) for each cell
-    /// Format: each cell is [char_byte, r, g, b]
+    /// Cells in row-major order, `format.cell_size()` bytes each
+    /// ASCII cells are [char_byte, r, g, b]; Unicode cells store the scalar
+    /// value in 3 little-endian bytes: [c0, c1, c2, r, g, b]
+    /// With a background, its color follows: [.., r, g, b, bg_r, bg_g, bg_b]
     pub data: Vec<u8>,
+    /// Older peers only know ASCII cells and omit this
+    #[serde(default)]
+    pub format: CellFormat,
 }
 
 impl AsciiFrame {
     pub fn new(width: u16, height: u16) -> Self {
+        Self::with_format(width, height, CellFormat::default())
+    }
+
+    pub fn with_format(width: u16, height: u16, format: CellFormat) -> Self {
         Self {
             width,
             height,
-            data: vec![0; width as usize * height as usize * 4],
+            data: vec![0; width as usize * height as usize * format.cell_size()],
+            format,
         }
     }
 
+    /// Number of cells in the frame
+    pub fn cell_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
     /// Set a cell at (x, y) with character and RGB color
+    ///
+    /// Setting a non-ASCII glyph on an ASCII frame converts it to Unicode cells.
     pub fn set_cell(&mut self, x: u16, y: u16, ch: char, r: u8, g: u8, b: u8) {
-        let idx = (y as usize * self.width as usize + x as usize) * 4;
-        if idx + 3 < self.data.len() {
-            self.data[idx] = ch as u8;
-            self.data[idx + 1] = r;
-            self.data[idx + 2] = g;
-            self.data[idx + 3] = b;
+        if !ch.is_ascii() && !self.format.unicode {
+            self.set_format(CellFormat {
+                unicode: true,
+                ..self.format
+            });
+        }
+
+        let size = self.format.cell_size();
+        let idx = (y as usize * self.width as usize + x as usize) * size;
+        if idx + size <= self.data.len() {
+            let glyph = self.format.glyph_size();
+            if self.format.unicode {
+                self.data[idx..idx + 3].copy_from_slice(&(ch as u32).to_le_bytes()[..3]);
+            } else {
+                self.data[idx] = ch as u8;
+            }
+            self.data[idx + glyph] = r;
+            self.data[idx + glyph + 1] = g;
+            self.data[idx + glyph + 2] = b;
         }
     }
 
     /// Get cell at (x, y)
     pub fn get_cell(&self, x: u16, y: u16) -> Option<(char, u8, u8, u8)> {
-        let idx = (y as usize * self.width as usize + x as usize) * 4;
-        if idx + 3 < self.data.len() {
+        let size = self.format.cell_size();
+        let idx = (y as usize * self.width as usize + x as usize) * size;
+        if idx + size <= self.data.len() {
+            let glyph = self.format.glyph_size();
+            let ch = if self.format.unicode {
+                let scalar = u32::from_le_bytes([self.data[idx], self.data[idx + 1], self.data[idx + 2], 0]);
+                char::from_u32(scalar).unwrap_or(char::REPLACEMENT_CHARACTER)
+            } else {
+                self.data[idx] as char
+            };
             Some((
-                self.data[idx] as char,
-                self.data[idx + 1],
-                self.data[idx + 2],
-                self.data[idx + 3],
+                ch,
+                self.data[idx + glyph],
+                self.data[idx + glyph + 1],
+                self.data[idx + glyph + 2],
             ))
         } else {
             None
         }
     }
+
+    /// Set the background color of a cell, for frames with backgrounds
+    pub fn set_background(&mut self, x: u16, y: u16, r: u8, g: u8, b: u8) {
+        let size = self.format.cell_size();
+        let idx = (y as usize * self.width as usize + x as usize) * size;
+        if self.format.background && idx + size <= self.data.len() {
+            self.data[idx + size - 3..idx + size].copy_from_slice(&[r, g, b]);
+        }
+    }
+
+    /// Get the background color of a cell, if the frame has backgrounds
+    pub fn get_background(&self, x: u16, y: u16) -> Option<(u8, u8, u8)> {
+        let size = self.format.cell_size();
+        let idx = (y as usize * self.width as usize + x as usize) * size;
+        if self.format.background && idx + size <= self.data.len() {
+            Some((self.data[idx + size - 3], self.data[idx + size - 2], self.data[idx + size - 1]))
+        } else {
+            None
+        }
+    }
+
+    /// Re-encode all cells in another cell format
+    pub fn set_format(&mut self, format: CellFormat) {
+        if format == self.format {
+            return;
+        }
+
+        let mut converted = Self::with_format(self.width, self.height, format);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if let Some((ch, r, g, b)) = self.get_cell(x, y) {
+                    // Dropping to ASCII cells cannot keep other glyphs
+                    let ch = if format.unicode || ch.is_ascii() { ch } else { '?' };
+                    converted.set_cell(x, y, ch, r, g, b);
+                }
+                if let Some((r, g, b)) = self.get_background(x, y) {
+                    converted.set_background(x, y, r, g, b);
+                }
+            }
+        }
+        *self = converted;
+    }
+}
+
+/// Changed cells of a frame relative to an earlier frame of the same stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameDelta {
+    pub seq: u32,
+    pub base_seq: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Cell format of the runs, which always matches the base frame
+    #[serde(default)]
+    pub format: CellFormat,
+    pub runs: Vec<CellRun>,
+}
+
+/// Consecutive cells starting at cell index `start`, in `AsciiFrame::data` layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CellRun {
+    pub start: u32,
+    pub data: Vec<u8>,
+}
+
+/// Unchanged cells between two changed ones that are still folded into one run,
+/// since a new run costs more than a few repeated cells
+const RUN_MERGE_GAP: usize = 2;
+
+impl FrameDelta {
+    /// Diff two frames, or `None` if their dimensions differ
+    pub fn between(prev: &AsciiFrame, next: &AsciiFrame, base_seq: u32, seq: u32) -> Option<Self> {
+        if prev.width != next.width
+            || prev.height != next.height
+            || prev.format != next.format
+            || prev.data.len() != next.data.len()
+        {
+            return None;
+        }
+
+        let size = next.format.cell_size();
+        let cells = next.data.len() / size;
+        let changed = |i: usize| prev.data[i * size..(i + 1) * size] != next.data[i * size..(i + 1) * size];
+
+        let mut runs: Vec<CellRun> = Vec::new();
+        let mut i = 0;
+        while i < cells {
+            if !changed(i) {
+                i += 1;
+                continue;
+            }
+
+            // Extend the run while changes keep appearing within the merge gap
+            let start = i;
+            let mut end = i + 1;
+            let mut j = end;
+            while j < cells && j <= end + RUN_MERGE_GAP {
+                if changed(j) {
+                    end = j + 1;
+                }
+                j += 1;
+            }
+
+            runs.push(CellRun {
+                start: start as u32,
+                data: next.data[start * size..end * size].to_vec(),
+            });
+            i = end;
+        }
+
+        Some(Self {
+            seq,
+            base_seq,
+            width: next.width,
+            height: next.height,
+            format: next.format,
+            runs,
+        })
+    }
+
+    /// Apply the delta on top of its base frame
+    pub fn apply(&self, base: &mut AsciiFrame) -> anyhow::Result<()> {
+        if base.width != self.width || base.height != self.height || base.format != self.format {
+            anyhow::bail!(
+                "Delta is {}x{} {:?} but base frame is {}x{} {:?}",
+                self.width, self.height, self.format, base.width, base.height, base.format
+            );
+        }
+
+        let size = self.format.cell_size();
+        for run in &self.runs {
+            let offset = run.start as usize * size;
+            let end = offset + run.data.len();
+            if run.data.len() % size != 0 || end > base.data.len() {
+                anyhow::bail!("Cell run at {} is out of bounds", run.start);
+            }
+            base.data[offset..end].copy_from_slice(&run.data);
+        }
+
+        Ok(())
+    }
+
+    /// Number of cells carried by the delta
+    pub fn changed_cells(&self) -> usize {
+        self.runs.iter().map(|r| r.data.len()).sum::<usize>() / self.format.cell_size()
+    }
 }
 
 /// Information about a connected user
@@ -104,3 +473,93 @@ pub struct UserInfo {
     pub username: String,
     pub connected_at: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_cells() {
+        let mut frame = AsciiFrame::new(4, 2);
+        frame.set_cell(3, 1, '#', 1, 2, 3);
+        assert_eq!(frame.data.len(), 4 * 2 * 4);
+        assert_eq!(frame.get_cell(3, 1), Some(('#', 1, 2, 3)));
+        assert!(!frame.format.unicode);
+    }
+
+    #[test]
+    fn test_unicode_cells_roundtrip() {
+        let mut frame = AsciiFrame::new(4, 2);
+        frame.set_cell(0, 0, 'a', 9, 9, 9);
+        frame.set_cell(1, 0, '\u{2588}', 10, 20, 30);
+        frame.set_cell(2, 0, '\u{28ff}', 40, 50, 60);
+        frame.set_cell(3, 0, '\u{1f600}', 70, 80, 90);
+
+        // The first non-ASCII glyph converts the frame, keeping earlier cells
+        assert!(frame.format.unicode);
+        assert_eq!(frame.data.len(), 4 * 2 * 6);
+        assert_eq!(frame.get_cell(0, 0), Some(('a', 9, 9, 9)));
+        assert_eq!(frame.get_cell(1, 0), Some(('\u{2588}', 10, 20, 30)));
+        assert_eq!(frame.get_cell(2, 0), Some(('\u{28ff}', 40, 50, 60)));
+        assert_eq!(frame.get_cell(3, 0), Some(('\u{1f600}', 70, 80, 90)));
+    }
+
+    #[test]
+    fn test_background_cells() {
+        let format = CellFormat {
+            unicode: false,
+            background: true,
+        };
+        let mut frame = AsciiFrame::with_format(2, 1, format);
+        frame.set_cell(1, 0, '#', 1, 2, 3);
+        frame.set_background(1, 0, 4, 5, 6);
+        frame.set_cell(0, 0, '\u{2580}', 7, 8, 9);
+
+        assert_eq!(frame.format.cell_size(), 9);
+        assert_eq!(frame.get_cell(1, 0), Some(('#', 1, 2, 3)));
+        assert_eq!(frame.get_background(1, 0), Some((4, 5, 6)));
+        assert_eq!(frame.get_cell(0, 0), Some(('\u{2580}', 7, 8, 9)));
+        assert_eq!(AsciiFrame::new(1, 1).get_background(0, 0), None);
+        assert_eq!(CellFormat::from_flags(frame.format.flags()).unwrap(), frame.format);
+    }
+
+    #[test]
+    fn test_limit_to_legacy_cells() {
+        let mut frame = AsciiFrame::new(2, 1);
+        frame.set_cell(0, 0, '\u{2588}', 1, 2, 3);
+        frame.set_cell(1, 0, 'a', 4, 5, 6);
+
+        let legacy = CellFormat::default();
+        assert!(!legacy.reads(frame.format));
+        frame.set_format(frame.format.limited_to(legacy));
+        assert!(legacy.reads(frame.format));
+        assert_eq!(frame.data.len(), 2 * 4);
+        assert_eq!(frame.get_cell(0, 0), Some(('?', 1, 2, 3)));
+        assert_eq!(frame.get_cell(1, 0), Some(('a', 4, 5, 6)));
+    }
+
+    #[test]
+    fn test_limit_background_cells() {
+        // Terminal screens always carry backgrounds
+        let mut screen = crate::vt::Screen::new(3, 1);
+        screen.feed(b"\x1b[?25l\x1b[44mab\x1b[0m");
+        let mut frame = screen.to_frame();
+
+        let unicode_only = CellFormat {
+            unicode: true,
+            background: false,
+        };
+        assert!(!unicode_only.reads(frame.format));
+        frame.set_format(frame.format.limited_to(unicode_only));
+        assert_eq!(frame.format, CellFormat::default());
+        assert_eq!(frame.data.len(), 3 * 4);
+        assert_eq!(frame.get_cell(1, 0), Some(('b', 229, 229, 229)));
+        assert_eq!(frame.get_background(1, 0), None);
+    }
+
+    #[test]
+    fn test_legacy_frame_json_is_ascii() {
+        let frame: AsciiFrame = serde_json::from_str(r#"{"width":1,"height":1,"data":[64,1,2,3]}"#).unwrap();
+        assert_eq!(frame.get_cell(0, 0), Some(('@', 1, 2, 3)));
+    }
+}
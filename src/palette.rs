@@ -0,0 +1,169 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use crate::glyphs;
+
+/// Standard ASCII character palette ordered from light to dark density
+/// 68 characters provide good granularity for luminance mapping
+pub const STANDARD: &[u8] = b" .'`^\",:;Il!i><~+_-?][}{1)(|\\tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$";
+
+/// Short ramp of the classic 10-character palette
+const CLASSIC: &str = " .:-=+*#%@";
+
+/// Unicode shade blocks
+const BLOCKS: &str = " \u{2591}\u{2592}\u{2593}\u{2588}";
+
+const DIGITS: &str = "0123456789";
+
+/// Names accepted by `Palette::builtin`
+pub const BUILTIN: &[&str] = &["standard", "classic", "blocks", "digits"];
+
+/// Glyphs that luminance is mapped onto, ordered from light to dark
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    glyphs: Vec<char>,
+}
+
+impl Palette {
+    /// Create a palette from glyphs already ordered from light to dark
+    pub fn new(name: impl Into<String>, glyphs: Vec<char>) -> Result<Self> {
+        if glyphs.len() < 2 {
+            bail!("A palette needs at least two glyphs");
+        }
+        Ok(Self {
+            name: name.into(),
+            glyphs,
+        })
+    }
+
+    /// The standard palette, fine grained for true color output
+    pub fn standard() -> Self {
+        Self {
+            name: "standard".to_string(),
+            glyphs: STANDARD.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    /// Look up a built-in palette by name
+    pub fn builtin(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        let glyphs = match name.as_str() {
+            "standard" => return Some(Self::standard()),
+            "classic" => CLASSIC.chars().collect(),
+            "blocks" => BLOCKS.chars().collect(),
+            // Digit shapes have no natural order, so measure them
+            "digits" => sorted_by_density(DIGITS.chars().collect()),
+            _ => return None,
+        };
+        Some(Self { name, glyphs })
+    }
+
+    /// Load a palette from a UTF-8 file listing its glyphs in any order
+    ///
+    /// Line breaks and tabs are ignored and repeated glyphs are dropped. The
+    /// glyphs are sorted by measured density, so the file needs no ordering.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read palette file {}", path.display()))?;
+
+        let mut glyphs: Vec<char> = Vec::new();
+        for ch in text.chars().filter(|c| *c == ' ' || !c.is_control()) {
+            if !glyphs.contains(&ch) {
+                glyphs.push(ch);
+            }
+        }
+
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "custom".to_string());
+        Self::new(name, sorted_by_density(glyphs))
+    }
+
+    /// A built-in palette name or the path of a palette file
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        match Self::builtin(spec) {
+            Some(palette) => Ok(palette),
+            None => Self::load(spec),
+        }
+    }
+
+    /// All built-in palettes
+    pub fn builtins() -> Vec<Self> {
+        BUILTIN.iter().filter_map(|name| Self::builtin(name)).collect()
+    }
+
+    /// Number of glyphs, and so of brightness levels
+    pub fn levels(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    /// Glyph of a level in `0..levels()`
+    pub fn glyph(&self, level: usize) -> char {
+        self.glyphs[level.min(self.glyphs.len() - 1)]
+    }
+
+    /// Glyph for a luminance value
+    pub fn glyph_for(&self, luminance: u8) -> char {
+        self.glyph(luminance as usize * (self.glyphs.len() - 1) / 255)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+/// Order glyphs from light to dark by measured ink coverage
+///
+/// Glyphs that cannot be measured are treated as half covered; equally dense
+/// glyphs keep their order.
+fn sorted_by_density(mut glyphs: Vec<char>) -> Vec<char> {
+    let unknown: String = glyphs.iter().filter(|c| glyphs::density(**c).is_none()).collect();
+    if !unknown.is_empty() {
+        tracing::warn!("Cannot measure the density of palette glyphs {:?}, assuming half coverage", unknown);
+    }
+
+    glyphs.sort_by(|a, b| {
+        let density = |c: &char| glyphs::density(*c).unwrap_or(0.5);
+        density(a).total_cmp(&density(b))
+    });
+    glyphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins() {
+        for name in BUILTIN {
+            let palette = Palette::builtin(name).unwrap();
+            assert_eq!(&palette.name, name);
+            assert!(palette.levels() >= 2);
+        }
+        assert_eq!(Palette::standard().levels(), STANDARD.len());
+        assert_eq!(Palette::builtin("Classic").unwrap().glyph_for(255), '@');
+        assert_eq!(Palette::builtin("blocks").unwrap().glyph_for(0), ' ');
+        assert!(Palette::builtin("nope").is_none());
+
+        let digits = Palette::builtin("digits").unwrap();
+        assert_eq!(digits.glyph(0), '3');
+        assert_eq!(digits.glyph(9), '8');
+    }
+
+    #[test]
+    fn test_load_sorts_by_density() {
+        let path = std::env::temp_dir().join(format!("termio-palette-{}.txt", std::process::id()));
+        std::fs::write(&path, "#@\n. :\u{2588}:\n").unwrap();
+        let palette = Palette::from_spec(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let palette = palette.unwrap();
+        let glyphs: String = (0..palette.levels()).map(|i| palette.glyph(i)).collect();
+        assert_eq!(glyphs, " .:#@\u{2588}");
+        assert!(Palette::new("one", vec!['x']).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::thread;
+use std::time::Instant;
+
+use anyhow::Result;
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use crate::image::RgbImage;
+use crate::message::{AsciiFrame, PixelFrame};
+use crate::pipeline::FramePipeline;
+use crate::source::FrameSource;
+use crate::webcam::{WebcamCommand, WebcamConfig};
+
+/// 75% color bars, as on a broadcast test card
+const BARS: [(u8, u8, u8); 7] = [
+    (191, 191, 191),
+    (191, 191, 0),
+    (0, 191, 191),
+    (0, 191, 0),
+    (191, 0, 191),
+    (191, 0, 0),
+    (0, 0, 191),
+];
+
+/// Color of a hue from 0.0 to 1.0 at full saturation
+fn hue(h: f32) -> (u8, u8, u8) {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let x = ((1.0 - (h % 2.0 - 1.0).abs()) * 255.0).round() as u8;
+    match h as u32 {
+        0 => (255, x, 0),
+        1 => (x, 255, 0),
+        2 => (0, 255, x),
+        3 => (0, x, 255),
+        4 => (x, 0, 255),
+        _ => (255, 0, x),
+    }
+}
+
+/// Position moving one unit per step and bouncing between 0 and `range`
+fn bounce(step: u64, range: usize) -> usize {
+    if range == 0 {
+        return 0;
+    }
+    let t = (step % (2 * range as u64)) as usize;
+    if t <= range {
+        t
+    } else {
+        2 * range - t
+    }
+}
+
+/// Test card image for frame number `index`
+///
+/// Color bars fill the top, with a gray ramp and a hue sweep scrolling in
+/// opposite directions below them, and a white box bouncing over it all.
+/// `pixel_aspect` is the height of a pixel on screen divided by its width, so
+/// the box stays square on cells that are not.
+pub fn render(index: u64, width: usize, height: usize, pixel_aspect: f32) -> RgbImage {
+    let mut image = RgbImage::new(width, height);
+    if width == 0 || height == 0 {
+        return image;
+    }
+
+    let bars_end = height * 3 / 5;
+    let ramp_end = height * 4 / 5;
+    let shift = index as usize * 4;
+    for y in 0..height {
+        for x in 0..width {
+            let color = if y < bars_end {
+                BARS[x * BARS.len() / width]
+            } else if y < ramp_end {
+                let v = ((x * 256 / width + shift) % 256) as u8;
+                (v, v, v)
+            } else {
+                hue((x as f32 / width as f32) - shift as f32 / 256.0)
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+
+    let box_h = (height / 4).max(1);
+    let box_w = ((box_h as f32 * pixel_aspect).round() as usize).clamp(1, width);
+    let left = bounce(index * 2, width - box_w);
+    let top = bounce(index, height - box_h);
+    for y in top..top + box_h {
+        for x in left..left + box_w {
+            image.set_pixel(x, y, (255, 255, 255));
+        }
+    }
+    image
+}
+
+/// Write the frame number into the top left cells, so dropped or repeated
+/// frames can be spotted by eye or by a test
+pub fn stamp_counter(frame: &mut AsciiFrame, index: u64) {
+    for (x, ch) in format!("{:06}", index).chars().enumerate() {
+        if x >= frame.width as usize {
+            break;
+        }
+        frame.set_cell(x as u16, 0, ch, 255, 255, 255);
+        frame.set_background(x as u16, 0, 0, 0, 0);
+    }
+}
+
+/// Generated video that needs no camera, for demos and tests
+///
+/// Frames go through the same filters, privacy mode, conversion and denoising
+/// as camera frames, and the source answers the same commands.
+pub struct TestPattern {
+    receiver: Receiver<AsciiFrame>,
+    pixel_receiver: Receiver<PixelFrame>,
+    commands: Sender<WebcamCommand>,
+}
+
+impl TestPattern {
+    pub fn start(config: WebcamConfig) -> Result<Self> {
+        let (tx, rx) = bounded::<AsciiFrame>(2);
+        let (pixel_tx, pixel_rx) = bounded::<PixelFrame>(2);
+        let (cmd_tx, cmd_rx) = bounded::<WebcamCommand>(4);
+
+        thread::spawn(move || {
+            if let Err(e) = Self::generate_loop(&config, &tx, &pixel_tx, &cmd_rx) {
+                tracing::error!("Test pattern error: {}", e);
+            }
+        });
+
+        Ok(Self {
+            receiver: rx,
+            pixel_receiver: pixel_rx,
+            commands: cmd_tx,
+        })
+    }
+
+    fn generate_loop(
+        config: &WebcamConfig,
+        tx: &Sender<AsciiFrame>,
+        pixel_tx: &Sender<PixelFrame>,
+        cmd_rx: &Receiver<WebcamCommand>,
+    ) -> Result<()> {
+        let frame_duration = config.frame_interval();
+
+        let (pixel_width, pixel_height) = config.convert.mode.pixel_size(config.width, config.height);
+        let pixel_aspect = if config.width > 0 && config.height > 0 {
+            (config.height as f32 * config.cell_aspect / pixel_height as f32) / (config.width as f32 / pixel_width as f32)
+        } else {
+            1.0
+        };
+        let mut pipeline = FramePipeline::new(config)?;
+
+        let mut next = Instant::now();
+        for index in 0.. {
+            if !pipeline.handle_commands(cmd_rx)? {
+                return Ok(());
+            }
+
+            let image = render(index, pixel_width as usize, pixel_height as usize, pixel_aspect);
+            let mut frame = pipeline.frame(image);
+            stamp_counter(&mut frame, index);
+
+            // Pixel frames are drawn at their own size, with square pixels
+            if pipeline.wants_pixels() {
+                let image = render(index, config.pixel_width as usize, config.pixel_height as usize, 1.0);
+                if let Some(pixels) = pipeline.pixel_frame(image) {
+                    let _ = pixel_tx.try_send(pixels);
+                }
+            }
+
+            if tx.send(frame).is_err() {
+                return Ok(());
+            }
+
+            // Keep to the frame rate without drifting, but never try to catch up
+            next += frame_duration;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                next = now;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FrameSource for TestPattern {
+    fn try_recv(&self) -> Option<AsciiFrame> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn try_recv_pixels(&self) -> Option<PixelFrame> {
+        self.pixel_receiver.try_recv().ok()
+    }
+
+    fn commands(&self) -> Option<Sender<WebcamCommand>> {
+        Some(self.commands.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let first = render(0, 70, 40, 1.0);
+        // Bars, ramp and sweep in their bands
+        assert_eq!(first.pixel(35, 20), (0, 191, 0));
+        assert_eq!(first.pixel(69, 20), (0, 0, 191));
+        let (r, g, b) = first.pixel(0, 28);
+        assert!(r == g && g == b);
+        assert_eq!(first.pixel(0, 35), (255, 0, 0));
+        // The box starts in the top left corner, and moves
+        assert_eq!(first.pixel(0, 0), (255, 255, 255));
+        assert_eq!(first.pixel(9, 9), (255, 255, 255));
+        assert_ne!(first.pixel(10, 0), (255, 255, 255));
+        let later = render(5, 70, 40, 1.0);
+        assert_eq!(later.pixel(10, 5), (255, 255, 255));
+        assert_ne!(later.pixel(0, 0), (255, 255, 255));
+        // and bounces back off the far edge
+        assert_eq!(bounce(12, 10), 8);
+    }
+
+    #[test]
+    fn test_source_sends_numbered_frames() {
+        let config = WebcamConfig {
+            fps_cap: 100,
+            ..WebcamConfig::default()
+        };
+        let source = TestPattern::start(config).unwrap();
+
+        let mut counters = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while counters.len() < 2 && Instant::now() < deadline {
+            match source.try_recv() {
+                Some(frame) => {
+                    assert_eq!((frame.width, frame.height), (80, 24));
+                    let counter: String = (0..6).map(|x| frame.get_cell(x, 0).unwrap().0).collect();
+                    counters.push(counter);
+                }
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        assert_eq!(counters, ["000000", "000001"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_peers_receive_numbered_frames() {
+        use crate::client::TermIOClient;
+        use crate::server::TermIOServer;
+        use crate::source::NoSource;
+
+        // Find a free port, then serve on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = format!("127.0.0.1:{}", port);
+        let url = format!("ws://{}", addr);
+        tokio::spawn(async move { TermIOServer::new().run(&addr).await });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut peer = TermIOClient::new("peer".to_string(), url.clone());
+        while let Err(e) = peer.connect_with(Box::new(NoSource)).await {
+            assert!(Instant::now() < deadline, "server never came up: {}", e);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut sender = TermIOClient::new("pattern".to_string(), url);
+        let config = WebcamConfig {
+            fps_cap: 100,
+            ..WebcamConfig::default()
+        };
+        sender.connect_with(Box::new(TestPattern::start(config).unwrap())).await.unwrap();
+
+        // The peer sees the counter of the pattern go up, through keyframes and deltas
+        let mut counters: Vec<u64> = Vec::new();
+        while counters.len() < 3 && Instant::now() < deadline {
+            let counter = peer.last_frames.read().get("pattern").map(|frame| {
+                (0..6).map(|x| frame.get_cell(x, 0).unwrap().0).collect::<String>()
+            });
+            if let Some(counter) = counter {
+                let counter = counter.parse().unwrap();
+                if counters.last() != Some(&counter) {
+                    counters.push(counter);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(counters.len(), 3, "peer saw counters {:?}", counters);
+        assert!(counters.windows(2).all(|pair| pair[0] < pair[1]), "peer saw counters {:?}", counters);
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use crossbeam_channel::Receiver;
+
+use crate::ascii::{self, ConvertOptions};
+use crate::denoise::TemporalDenoise;
+use crate::filter::FilterChain;
+use crate::image::RgbImage;
+use crate::message::{AsciiFrame, PixelFormat, PixelFrame};
+use crate::privacy::MotionPrivacy;
+use crate::webcam::{WebcamCommand, WebcamConfig};
+
+/// Stages every camera-like image goes through on its way to viewers
+///
+/// Sources bring an image at the render mode's pixel size; the pipeline runs
+/// the filters, hides the background, converts to cells and denoises them, and
+/// answers the control commands that change these stages while running. New
+/// per-frame stages belong here, so every source gets them.
+pub struct FramePipeline {
+    config: WebcamConfig,
+    convert: ConvertOptions,
+    filters: FilterChain,
+    privacy: MotionPrivacy,
+    denoise: TemporalDenoise,
+    /// Pixel frame stages, while some viewer wants pixel frames
+    pixels: Option<PixelStages>,
+}
+
+/// Filters and privacy of the pixel frames, which keep their own state since
+/// their images differ in size from the cell images
+struct PixelStages {
+    /// Layout sent, the richest any viewer needs
+    format: PixelFormat,
+    filters: FilterChain,
+    privacy: MotionPrivacy,
+}
+
+impl FramePipeline {
+    pub fn new(config: &WebcamConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            convert: config.convert.clone(),
+            filters: config.filter_chain()?,
+            privacy: MotionPrivacy::new(config.privacy),
+            denoise: TemporalDenoise::new(config.denoise, &config.convert),
+            pixels: None,
+        })
+    }
+
+    /// Apply the commands sent since the last call; `false` once asked to stop
+    pub fn handle_commands(&mut self, commands: &Receiver<WebcamCommand>) -> Result<bool> {
+        while let Ok(command) = commands.try_recv() {
+            match command {
+                WebcamCommand::Stop => return Ok(false),
+                WebcamCommand::SetPalette(palette) => {
+                    tracing::info!("Switching palette to {}", palette.name);
+                    self.convert.palette = palette;
+                    self.denoise.reset();
+                }
+                WebcamCommand::SetPrivacy(mode) => {
+                    tracing::info!("Switching privacy mode to {}", mode.name());
+                    self.privacy.mode = mode;
+                    if let Some(pixels) = self.pixels.as_mut() {
+                        pixels.privacy.mode = mode;
+                    }
+                }
+                WebcamCommand::SetPixels(Some(format)) => match self.pixels.as_mut() {
+                    Some(pixels) => pixels.format = format,
+                    None => {
+                        tracing::info!("Producing {:?} pixel frames for viewers", format);
+                        self.pixels = Some(PixelStages {
+                            format,
+                            filters: self.config.filter_chain()?,
+                            privacy: MotionPrivacy::new(self.privacy.mode),
+                        });
+                    }
+                },
+                WebcamCommand::SetPixels(None) => self.pixels = None,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether some viewer wants pixel frames, so the source should make images for them
+    pub fn wants_pixels(&self) -> bool {
+        self.pixels.is_some()
+    }
+
+    /// Turn an image of the render mode's pixel size into cells
+    pub fn frame(&mut self, mut image: RgbImage) -> AsciiFrame {
+        self.filters.apply_rgb(&mut image);
+        self.privacy.apply(&mut image);
+        let mut frame = ascii::convert(&image, self.config.width, self.config.height, &self.convert);
+        self.denoise.apply(&mut frame, &image);
+        self.filters.apply_frame(&mut frame);
+        frame
+    }
+
+    /// Turn an image of the pixel frame size into a pixel frame, `None` while
+    /// no viewer wants them
+    pub fn pixel_frame(&mut self, mut image: RgbImage) -> Option<PixelFrame> {
+        let pixels = self.pixels.as_mut()?;
+        pixels.filters.apply_rgb(&mut image);
+        pixels.privacy.apply(&mut image);
+        Some(image.to_pixel_frame(pixels.format))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palette;
+    use crossbeam_channel::bounded;
+
+    #[test]
+    fn test_commands() {
+        let config = WebcamConfig {
+            width: 2,
+            height: 1,
+            ..WebcamConfig::default()
+        };
+        let mut pipeline = FramePipeline::new(&config).unwrap();
+        let (tx, rx) = bounded(4);
+
+        let digits = Palette::builtin("digits").unwrap();
+        tx.send(WebcamCommand::SetPalette(digits.clone())).unwrap();
+        tx.send(WebcamCommand::SetPixels(Some(PixelFormat::Luma))).unwrap();
+        assert!(pipeline.handle_commands(&rx).unwrap());
+        let mut image = RgbImage::new(2, 1);
+        image.data.fill(255);
+        let frame = pipeline.frame(image);
+        assert_eq!(frame.get_cell(0, 0).unwrap().0, digits.glyph_for(255));
+        let pixels = pipeline.pixel_frame(RgbImage::new(4, 3)).unwrap();
+        assert_eq!((pixels.width, pixels.height, pixels.format), (4, 3, PixelFormat::Luma));
+
+        tx.send(WebcamCommand::SetPixels(None)).unwrap();
+        tx.send(WebcamCommand::Stop).unwrap();
+        assert!(!pipeline.handle_commands(&rx).unwrap());
+        assert!(!pipeline.wants_pixels());
+    }
+}
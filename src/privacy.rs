@@ -0,0 +1,244 @@
+use crate::ascii::luminance;
+use crate::image::RgbImage;
+
+/// Regions across and down the image that are judged foreground or background
+const GRID_COLS: usize = 16;
+const GRID_ROWS: usize = 12;
+
+/// Share of a region's motion kept from one frame to the next
+///
+/// Slow decay keeps someone who sits still counted as foreground for a few
+/// seconds at 30 fps.
+const ACTIVITY_DECAY: f32 = 0.97;
+
+/// Mean brightness change per pixel above which a region counts as moving
+const MOTION_THRESHOLD: f32 = 6.0;
+
+/// Brightness kept in dimmed background regions
+const DIM_LEVEL: f32 = 0.25;
+
+/// How static background is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrivacyMode {
+    #[default]
+    Off,
+    /// Background regions become a single averaged color each
+    Pixelate,
+    /// Background regions are darkened
+    Dim,
+}
+
+impl PrivacyMode {
+    /// The mode after this one, for cycling with a key
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Pixelate,
+            Self::Pixelate => Self::Dim,
+            Self::Dim => Self::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Pixelate => "pixelate",
+            Self::Dim => "dim",
+        }
+    }
+}
+
+impl std::str::FromStr for PrivacyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(Self::Off),
+            "pixelate" | "blur" => Ok(Self::Pixelate),
+            "dim" => Ok(Self::Dim),
+            other => Err(anyhow::anyhow!("Unknown privacy mode: {}", other)),
+        }
+    }
+}
+
+/// Hides the parts of the picture that never move
+///
+/// There is no person detection: the image is split into a coarse grid, and a
+/// region is foreground while it has recently seen enough frame to frame
+/// change. Everything else is treated as the room and pixelated or dimmed.
+/// Until someone moves the whole picture counts as background.
+pub struct MotionPrivacy {
+    pub mode: PrivacyMode,
+    /// Luminance of the previous image
+    previous: Vec<u8>,
+    /// Decaying motion of each grid region
+    activity: Vec<f32>,
+    size: (usize, usize),
+}
+
+impl MotionPrivacy {
+    pub fn new(mode: PrivacyMode) -> Self {
+        Self {
+            mode,
+            previous: Vec::new(),
+            activity: vec![0.0; GRID_COLS * GRID_ROWS],
+            size: (0, 0),
+        }
+    }
+
+    /// Pixel bounds of a grid region, empty when the image is smaller than the grid
+    fn region(&self, col: usize, row: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let (width, height) = self.size;
+        (
+            col * width / GRID_COLS..(col + 1) * width / GRID_COLS,
+            row * height / GRID_ROWS..(row + 1) * height / GRID_ROWS,
+        )
+    }
+
+    /// Update the motion statistics, then hide the background of the image
+    pub fn apply(&mut self, image: &mut RgbImage) {
+        if self.mode == PrivacyMode::Off {
+            // Start over when switched back on, rather than trust stale motion
+            self.previous.clear();
+            return;
+        }
+
+        let luma: Vec<u8> = image.data.chunks_exact(3).map(|p| luminance(p[0], p[1], p[2])).collect();
+        if self.size != (image.width, image.height) || self.previous.len() != luma.len() {
+            self.size = (image.width, image.height);
+            self.activity.fill(0.0);
+            self.previous = luma.clone();
+        }
+
+        let width = image.width;
+        for row in 0..GRID_ROWS {
+            for col in 0..GRID_COLS {
+                let (xs, ys) = self.region(col, row);
+                let mut change = 0u32;
+                let mut count = 0u32;
+                for y in ys {
+                    for x in xs.clone() {
+                        let i = y * width + x;
+                        change += luma[i].abs_diff(self.previous[i]) as u32;
+                        count += 1;
+                    }
+                }
+                let motion = if count > 0 { change as f32 / count as f32 } else { 0.0 };
+                let activity = &mut self.activity[row * GRID_COLS + col];
+                *activity = (*activity * ACTIVITY_DECAY).max(motion);
+            }
+        }
+        self.previous = luma;
+
+        for row in 0..GRID_ROWS {
+            for col in 0..GRID_COLS {
+                if !self.is_foreground(col, row) {
+                    self.hide(image, col, row);
+                }
+            }
+        }
+    }
+
+    /// Whether a region or one of its neighbours is moving
+    ///
+    /// Growing the moving area by a region keeps the edges of a person, which
+    /// change less than their middle, from being hidden.
+    fn is_foreground(&self, col: usize, row: usize) -> bool {
+        let rows = row.saturating_sub(1)..=(row + 1).min(GRID_ROWS - 1);
+        rows.into_iter().any(|r| {
+            let cols = col.saturating_sub(1)..=(col + 1).min(GRID_COLS - 1);
+            cols.into_iter().any(|c| self.activity[r * GRID_COLS + c] > MOTION_THRESHOLD)
+        })
+    }
+
+    fn hide(&self, image: &mut RgbImage, col: usize, row: usize) {
+        let (xs, ys) = self.region(col, row);
+        match self.mode {
+            PrivacyMode::Off => {}
+            PrivacyMode::Pixelate => {
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        let (r, g, b) = image.pixel(x, y);
+                        sum[0] += r as u32;
+                        sum[1] += g as u32;
+                        sum[2] += b as u32;
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    return;
+                }
+                let [r, g, b] = sum.map(|c| (c / count) as u8);
+                for y in ys {
+                    for x in xs.clone() {
+                        image.set_pixel(x, y, (r, g, b));
+                    }
+                }
+            }
+            PrivacyMode::Dim => {
+                for y in ys {
+                    for x in xs.clone() {
+                        let (r, g, b) = image.pixel(x, y);
+                        let dim = |c: u8| (c as f32 * DIM_LEVEL).round() as u8;
+                        image.set_pixel(x, y, (dim(r), dim(g), dim(b)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Striped 32x24 room with a bright square whose position is given
+    fn scene(square_x: usize) -> RgbImage {
+        let mut image = RgbImage::new(32, 24);
+        for y in 0..24 {
+            for x in 0..32 {
+                let v = if x % 2 == 0 { 40 } else { 200 };
+                image.set_pixel(x, y, (v, v, v));
+            }
+        }
+        for y in 10..14 {
+            for x in square_x..square_x + 4 {
+                image.set_pixel(x, y, (250, 50, 50));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_static_room_is_hidden_moving_parts_kept() {
+        let mut privacy = MotionPrivacy::new(PrivacyMode::Pixelate);
+        for frame in 0..6 {
+            let mut image = scene(8 + frame % 2 * 2);
+            privacy.apply(&mut image);
+            if frame == 5 {
+                // Far from the square the stripes are averaged away
+                assert_eq!(image.pixel(28, 2), image.pixel(29, 2));
+                // The moving square is untouched
+                assert_eq!(image.pixel(11, 11), (250, 50, 50));
+                // and so is the still room right next to it
+                assert_ne!(image.pixel(14, 11), image.pixel(15, 11));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dim_and_off() {
+        let mut privacy = MotionPrivacy::new(PrivacyMode::Dim);
+        let mut image = scene(8);
+        privacy.apply(&mut image);
+        // Nothing has moved yet, so everything is background
+        assert_eq!(image.pixel(1, 0), (50, 50, 50));
+
+        let mut off = MotionPrivacy::new(PrivacyMode::Off);
+        let mut image = scene(8);
+        off.apply(&mut image);
+        assert_eq!(image, scene(8));
+        assert_eq!(PrivacyMode::Dim.next(), PrivacyMode::Off);
+    }
+}
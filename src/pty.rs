@@ -0,0 +1,248 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
+
+use crate::message::AsciiFrame;
+use crate::source::FrameSource;
+use crate::vt::Screen;
+use crate::webcam::WebcamConfig;
+
+/// A program run in a pseudo-terminal, whose screen is sent as video
+///
+/// The program sees a terminal of the configured cell size and draws on it as
+/// usual; its output is parsed into a `Screen`, which is sent whenever it
+/// changed, at most at the FPS cap. Frames have real glyphs and colors, so
+/// they skip the camera pipeline. When the program exits its last screen is
+/// sent and stays on viewers' tiles.
+pub struct PtySource {
+    receiver: Receiver<AsciiFrame>,
+    /// Never sent on; dropped with the source, which tells the thread that
+    /// nobody watches any more even while the screen stays unchanged
+    _closed: Sender<()>,
+}
+
+impl PtySource {
+    /// Run a shell command line in a new pseudo-terminal
+    pub fn start(config: &WebcamConfig, command: &str) -> Result<Self> {
+        let (width, height) = (config.width.max(1), config.height.max(1));
+        let (master, slave) = open_pty(width, height)?;
+        let mut shell = Command::new("sh");
+        shell
+            .arg("-c")
+            .arg(command)
+            .env("TERM", "xterm-256color")
+            .env("COLUMNS", width.to_string())
+            .env("LINES", height.to_string())
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+        // Make the program a session leader with the pseudo-terminal as its
+        // controlling terminal, so job control and signals work as usual
+        // SAFETY: only async-signal-safe calls run between fork and exec
+        unsafe {
+            shell.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = shell.spawn().with_context(|| format!("Failed to run {}", command))?;
+        // The parent's copies of the slave go with `shell`, so reading the
+        // master fails once the program exits
+        drop(shell);
+
+        let (tx, rx) = bounded::<AsciiFrame>(2);
+        let (closed_tx, closed_rx) = bounded::<()>(0);
+        let frame_duration = config.frame_interval();
+
+        thread::spawn(move || {
+            let screen = Screen::new(width, height);
+            if let Err(e) = Self::share_loop(master, child, screen, frame_duration, &tx, &closed_rx) {
+                tracing::error!("Terminal source error: {}", e);
+            }
+        });
+
+        Ok(Self {
+            receiver: rx,
+            _closed: closed_tx,
+        })
+    }
+
+    fn share_loop(
+        master: OwnedFd,
+        mut child: Child,
+        mut screen: Screen,
+        frame_duration: Duration,
+        tx: &Sender<AsciiFrame>,
+        closed: &Receiver<()>,
+    ) -> Result<()> {
+        let fd = master.as_raw_fd();
+        let mut buf = [0u8; 4096];
+        let mut next_frame = Instant::now();
+
+        loop {
+            let wait = next_frame.saturating_duration_since(Instant::now());
+            let mut poll = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `poll` points at one valid pollfd for the duration of the call
+            let ready = unsafe { libc::poll(&mut poll, 1, wait.as_millis() as i32) };
+            if ready > 0 {
+                // SAFETY: reads at most `buf.len()` bytes into `buf`
+                let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+                if n <= 0 {
+                    if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    // Reading fails with EIO once the program and its children are gone
+                    break;
+                }
+                screen.feed(&buf[..n as usize]);
+
+                let replies = screen.take_replies();
+                if !replies.is_empty() {
+                    // SAFETY: writes `replies.len()` bytes from `replies`
+                    unsafe { libc::write(fd, replies.as_ptr().cast(), replies.len()) };
+                }
+            }
+
+            if Instant::now() >= next_frame {
+                // The source was dropped, so nobody watches any more. Polling
+                // wakes up at least once a frame, so a quiet program is noticed too.
+                let gone = closed.try_recv() == Err(TryRecvError::Disconnected);
+                if gone || (screen.take_dirty() && tx.send(screen.to_frame()).is_err()) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Ok(());
+                }
+                next_frame = Instant::now() + frame_duration;
+            }
+        }
+
+        // The final screen stays on viewers' tiles
+        let _ = tx.send(screen.to_frame());
+        let status = child.wait()?;
+        tracing::info!("Shared terminal program exited: {}", status);
+        Ok(())
+    }
+}
+
+impl FrameSource for PtySource {
+    fn try_recv(&self) -> Option<AsciiFrame> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Open a pseudo-terminal of the given size, returning its master and slave
+fn open_pty(width: u16, height: u16) -> Result<(OwnedFd, File)> {
+    let os_error = |what: &str| anyhow!("{}: {}", what, io::Error::last_os_error());
+
+    // SAFETY: plain libc call, the returned descriptor is owned below
+    let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+    if fd < 0 {
+        return Err(os_error("Failed to open a pseudo-terminal"));
+    }
+    // SAFETY: `fd` is a fresh descriptor nothing else owns
+    let master = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: `fd` is an open pseudo-terminal master
+    if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+        return Err(os_error("Failed to unlock the pseudo-terminal"));
+    }
+    // SAFETY: `ptsname` returns a NUL terminated static buffer, copied at once
+    let name = unsafe { libc::ptsname(fd) };
+    if name.is_null() {
+        return Err(os_error("Failed to name the pseudo-terminal"));
+    }
+    // SAFETY: checked for NULL above
+    let path = PathBuf::from(unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().into_owned());
+
+    let size = libc::winsize {
+        ws_row: height,
+        ws_col: width,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: `size` is a valid winsize for the duration of the call
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(os_error("Failed to size the pseudo-terminal"));
+    }
+
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok((master, slave))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_output_becomes_frames() {
+        let config = WebcamConfig {
+            width: 20,
+            height: 4,
+            ..WebcamConfig::default()
+        };
+        let source = PtySource::start(&config, "printf '\\033[31mhello\\033[0m'; stty size").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut last = None;
+        let mut row = String::new();
+        while !row.starts_with("hello4 20") && Instant::now() < deadline {
+            match source.try_recv() {
+                Some(frame) => {
+                    row = (0..frame.width).map(|x| frame.get_cell(x, 0).unwrap().0).collect();
+                    last = Some(frame);
+                }
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        // The program drew in color, on a terminal of the configured size
+        assert!(row.starts_with("hello4 20"), "screen showed {:?}", row);
+        assert_eq!(last.unwrap().get_cell(0, 0), Some(('h', 205, 0, 0)));
+    }
+
+    #[test]
+    fn test_quiet_program_killed_when_dropped() {
+        let pid_file = std::env::temp_dir().join(format!("termio-pty-{}", std::process::id()));
+        let command = format!("echo $$ > {}; exec sleep 30", pid_file.display());
+        let source = PtySource::start(&WebcamConfig::default(), &command).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let pid = loop {
+            let pid = std::fs::read_to_string(&pid_file).ok().and_then(|s| s.trim().parse::<i32>().ok());
+            match pid {
+                Some(pid) => break pid,
+                None if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                None => panic!("program never started"),
+            }
+        };
+        let _ = std::fs::remove_file(&pid_file);
+
+        // The program prints nothing more, yet goes away with the source
+        drop(source);
+        // SAFETY: signal 0 only checks that the process exists
+        let alive = || unsafe { libc::kill(pid, 0) } == 0;
+        while alive() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!alive(), "program {} still runs", pid);
+    }
+}
@@ -1,5 +1,7 @@
-use crate::message::Message;
+use crate::compress::{Compression, CompressionStats};
+use crate::message::{CellFormat, Encoding, Message};
 use crate::user::{User, UserManager};
+use crate::wire;
 use anyhow::Result;
 use futures::{SinkExt, StreamExt};
 use parking_lot::RwLock;
@@ -60,6 +62,18 @@ impl TermIOServer {
         let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
 
         let mut user: Option<User> = None;
+        // Legacy clients never announce an encoding, so they stay on JSON
+        let mut encoding = Encoding::Json;
+        // Whether this client can apply deltas; otherwise it is sent full frames
+        let mut deltas = false;
+        let mut compression = Compression::None;
+        // Whether this client wants pixel frames; others are never sent them
+        let mut pixels = false;
+        // Cell layouts this client reads; frames in richer ones are re-encoded
+        let mut cell_format = CellFormat::default();
+        let mut stats = CompressionStats::default();
+        // Whether we asked this sender for a keyframe and are still waiting on it
+        let mut keyframe_requested = false;
 
         // Handle incoming messages
         loop {
@@ -68,16 +82,35 @@ impl TermIOServer {
                     match msg {
                         Some(Ok(ws_msg)) => {
                             match ws_msg {
-                                WsMessage::Text(text) => {
-                                    match serde_json::from_str::<Message>(&text) {
+                                WsMessage::Text(_) | WsMessage::Binary(_) => {
+                                    match wire::from_ws(&ws_msg) {
                                         Ok(msg) => {
                                             match msg {
-                                                Message::Join { username } => {
+                                                Message::Join {
+                                                    username,
+                                                    encoding: requested,
+                                                    deltas: wants_deltas,
+                                                    compression: wants_compression,
+                                                    pixels: wants_pixels,
+                                                    pixel_format,
+                                                    cell_format: reads,
+                                                } => {
+                                                    cell_format = reads;
+                                                    encoding = requested;
+                                                    deltas = wants_deltas;
+                                                    compression = wants_compression;
+                                                    pixels = wants_pixels;
                                                     // Create and register user
                                                     let new_user = user_manager.add_user(username.clone()).await;
                                                     let user_id = new_user.id.clone();
+                                                    user_manager
+                                                        .set_pixels(&user_id, pixels.then_some(pixel_format))
+                                                        .await;
 
-                                                    tracing::info!("User {} joined: {}", user_id, username);
+                                                    tracing::info!(
+                                                        "User {} joined: {} ({:?}, {:?} compression)",
+                                                        user_id, username, encoding, compression
+                                                    );
 
                                                     // Store connection
                                                     connections.write().insert(user_id.clone(), tx.clone());
@@ -88,9 +121,7 @@ impl TermIOServer {
                                                         success: true,
                                                         message: format!("Welcome, {}!", username),
                                                     };
-                                                    let _ = ws_tx.send(WsMessage::Text(
-                                                        serde_json::to_string(&ack)?,
-                                                    )).await;
+                                                    let _ = ws_tx.send(wire::to_ws(&ack, encoding, compression)?).await;
 
                                                     // Broadcast user list to all
                                                     let user_list = user_manager.get_user_list().await;
@@ -110,16 +141,34 @@ impl TermIOServer {
                                                         &joined,
                                                         &user_id,
                                                     )?;
+
+                                                    // Tell senders, the newcomer included, whether to capture pixels
+                                                    Self::broadcast_pixel_demand(&user_manager, &connections).await;
                                                 }
-                                                Message::Frame { frame, user_id: _, username: _ } => {
+                                                Message::PixelFrame { frame, .. } => {
                                                     if let Some(ref u) = user {
-                                                        u.update_frame(frame.clone()).await;
+                                                        let pixel_msg = Message::PixelFrame {
+                                                            user_id: u.id.clone(),
+                                                            username: u.username.clone(),
+                                                            frame,
+                                                        };
+                                                        Self::broadcast_to_all(
+                                                            &connections,
+                                                            &pixel_msg,
+                                                        )?;
+                                                    }
+                                                }
+                                                Message::Frame { frame, seq, user_id: _, username: _ } => {
+                                                    if let Some(ref u) = user {
+                                                        u.update_frame(frame.clone(), seq).await;
+                                                        keyframe_requested = false;
 
                                                         // Broadcast to ALL including the sender
                                                         let frame_msg = Message::Frame {
                                                             user_id: u.id.clone(),
                                                             username: u.username.clone(),
                                                             frame: frame.clone(),
+                                                            seq,
                                                         };
                                                         Self::broadcast_to_all(
                                                             &connections,
@@ -127,6 +176,42 @@ impl TermIOServer {
                                                         )?;
                                                     }
                                                 }
+                                                Message::FrameDelta { delta, .. } => {
+                                                    if let Some(ref u) = user {
+                                                        // Keep our copy current so late joiners and
+                                                        // legacy clients can be sent full frames
+                                                        if u.apply_delta(&delta).await {
+                                                            let delta_msg = Message::FrameDelta {
+                                                                user_id: u.id.clone(),
+                                                                username: u.username.clone(),
+                                                                delta,
+                                                            };
+                                                            Self::broadcast_to_all(
+                                                                &connections,
+                                                                &delta_msg,
+                                                            )?;
+                                                        } else if !keyframe_requested {
+                                                            // Ask once per gap; deltas already in flight fail too
+                                                            keyframe_requested = true;
+                                                            tracing::debug!("Delta from {} does not match, requesting keyframe", u.username);
+                                                            let _ = tx.send(Message::KeyframeRequest {
+                                                                user_id: u.id.clone(),
+                                                            });
+                                                        }
+                                                    }
+                                                }
+                                                Message::KeyframeRequest { user_id: target } => {
+                                                    if let Some(ref u) = user {
+                                                        for other in user_manager.get_other_users(&u.id).await {
+                                                            if !target.is_empty() && other.id != target {
+                                                                continue;
+                                                            }
+                                                            if let Some(frame_msg) = Self::keyframe_for(&other).await {
+                                                                let _ = tx.send(frame_msg);
+                                                            }
+                                                        }
+                                                    }
+                                                }
                                                 Message::Chat { content, .. } => {
                                                     if let Some(ref u) = user {
                                                         // Broadcast to all
@@ -142,9 +227,7 @@ impl TermIOServer {
                                                     }
                                                 }
                                                 Message::Ping => {
-                                                    let _ = ws_tx.send(WsMessage::Text(
-                                                        serde_json::to_string(&Message::Pong)?,
-                                                    )).await;
+                                                    let _ = ws_tx.send(wire::to_ws(&Message::Pong, encoding, compression)?).await;
                                                 }
                                                 _ => {}
                                             }
@@ -172,6 +255,7 @@ impl TermIOServer {
                                         let user_list = user_manager.get_user_list().await;
                                         let list_msg = Message::UserList(user_list);
                                         Self::broadcast_to_all(&connections, &list_msg)?;
+                                        Self::broadcast_pixel_demand(&user_manager, &connections).await;
                                     }
                                     break;
                                 }
@@ -190,8 +274,36 @@ impl TermIOServer {
                 }
 
                 Some(msg) = rx.recv() => {
-                    if let Ok(json) = serde_json::to_string(&msg) {
-                        let _ = ws_tx.send(WsMessage::Text(json)).await;
+                    // Clients without delta support, or that cannot read the
+                    // cells of a delta, get the rebuilt full frame instead
+                    let msg = match msg {
+                        Message::FrameDelta { user_id, delta, .. } if !deltas || !cell_format.reads(delta.format) => {
+                            let sender = user_manager.get_user(&user_id).await;
+                            match sender {
+                                Some(sender) => match Self::keyframe_for(&sender).await {
+                                    Some(frame_msg) => frame_msg,
+                                    None => continue,
+                                },
+                                None => continue,
+                            }
+                        }
+                        Message::PixelFrame { .. } if !pixels => continue,
+                        msg => msg,
+                    };
+                    let msg = match msg {
+                        Message::Frame { user_id, username, mut frame, seq } if !cell_format.reads(frame.format) => {
+                            frame.set_format(frame.format.limited_to(cell_format));
+                            Message::Frame { user_id, username, frame, seq }
+                        }
+                        msg => msg,
+                    };
+
+                    if let Ok(out) = wire::to_ws(&msg, encoding, compression) {
+                        if let (Some(raw), Compression::Rle | Compression::Deflate) = (msg.cell_bytes(), compression) {
+                            let label = user.as_ref().map_or("unknown", |u| u.username.as_str());
+                            stats.record(label, compression, raw, out.len());
+                        }
+                        let _ = ws_tx.send(out).await;
                     }
                 }
             }
@@ -211,11 +323,36 @@ impl TermIOServer {
             let user_list = user_manager.get_user_list().await;
             let list_msg = Message::UserList(user_list);
             let _ = Self::broadcast_to_all(&connections, &list_msg);
+            Self::broadcast_pixel_demand(&user_manager, &connections).await;
         }
 
         Ok(())
     }
 
+    /// Build a full frame message from a user's latest frame
+    async fn keyframe_for(user: &User) -> Option<Message> {
+        let (seq, frame) = user.get_keyframe().await?;
+        Some(Message::Frame {
+            user_id: user.id.clone(),
+            username: user.username.clone(),
+            frame,
+            seq,
+        })
+    }
+
+    /// Tell every client whether anyone still wants pixel frames
+    async fn broadcast_pixel_demand(
+        user_manager: &UserManager,
+        connections: &Arc<RwLock<HashMap<String, mpsc::UnboundedSender<Message>>>>,
+    ) {
+        let wanted = user_manager.wants_pixels().await;
+        let demand = Message::PixelDemand {
+            wanted: wanted.is_some(),
+            format: wanted.unwrap_or_default(),
+        };
+        let _ = Self::broadcast_to_all(connections, &demand);
+    }
+
     /// Broadcast a message to all connected users
     fn broadcast_to_all(
         connections: &Arc<RwLock<HashMap<String, mpsc::UnboundedSender<Message>>>>,
@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::Sender;
+
+use crate::avatar::AvatarSource;
+use crate::message::{AsciiFrame, PixelFrame};
+use crate::pattern::TestPattern;
+#[cfg(unix)]
+use crate::pty::PtySource;
+use crate::webcam::{Playback, WebcamCapture, WebcamCommand, WebcamConfig};
+
+/// Producer of the video a client sends
+///
+/// Sources run on their own thread and are polled by the client's sender task,
+/// which never blocks on them.
+pub trait FrameSource: Send {
+    /// Next frame, if one is ready
+    fn try_recv(&self) -> Option<AsciiFrame>;
+
+    /// Next pixel frame, if one is ready; sources without pixels never have one
+    fn try_recv_pixels(&self) -> Option<PixelFrame> {
+        None
+    }
+
+    /// Control channel, for sources that can change palette, privacy or pixel
+    /// output while running
+    fn commands(&self) -> Option<Sender<WebcamCommand>> {
+        None
+    }
+}
+
+/// Source that never produces a frame, for joining without video
+pub struct NoSource;
+
+impl FrameSource for NoSource {
+    fn try_recv(&self) -> Option<AsciiFrame> {
+        None
+    }
+}
+
+/// Built-in sources, as chosen on the command line
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum SourceSpec {
+    /// The camera named by `WebcamConfig::device`
+    #[default]
+    Webcam,
+    /// A video file played as if it were a camera, written `file:<path>`
+    File(Playback),
+    /// Program run in a pseudo-terminal, its screen shared, written `pty:<command>`
+    Terminal(String),
+    /// Still image or ASCII art text file, written `avatar:<path>`
+    Avatar(PathBuf),
+    /// Generated test card with a frame counter, for use without a camera
+    Pattern,
+    /// No video at all
+    None,
+}
+
+impl std::str::FromStr for SourceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("file:") {
+            return Ok(Self::File(Playback::new(path)));
+        }
+        if let Some(path) = s.strip_prefix("avatar:") {
+            return Ok(Self::Avatar(PathBuf::from(path)));
+        }
+        if let Some(command) = s.strip_prefix("pty:") {
+            return Ok(Self::Terminal(command.to_string()));
+        }
+        match s.to_ascii_lowercase().as_str() {
+            "webcam" | "camera" => Ok(Self::Webcam),
+            "pattern" | "test" => Ok(Self::Pattern),
+            "none" | "off" => Ok(Self::None),
+            other => Err(anyhow!("Unknown source: {}", other)),
+        }
+    }
+}
+
+impl SourceSpec {
+    /// Start the source with the given capture settings
+    pub fn start(&self, config: &WebcamConfig) -> Result<Box<dyn FrameSource>> {
+        match self {
+            Self::Webcam => Ok(Box::new(WebcamCapture::start(config.clone())?)),
+            Self::File(playback) => Ok(Box::new(WebcamCapture::start_file(config.clone(), playback.clone())?)),
+            Self::Avatar(path) => Ok(Box::new(AvatarSource::start(config, path)?)),
+            #[cfg(unix)]
+            Self::Terminal(command) => Ok(Box::new(PtySource::start(config, command)?)),
+            #[cfg(not(unix))]
+            Self::Terminal(_) => Err(anyhow!("Sharing a terminal needs a Unix pseudo-terminal")),
+            Self::Pattern => Ok(Box::new(TestPattern::start(config.clone())?)),
+            Self::None => Ok(Box::new(NoSource)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_start() {
+        assert_eq!("webcam".parse::<SourceSpec>().unwrap(), SourceSpec::Webcam);
+        assert_eq!("None".parse::<SourceSpec>().unwrap(), SourceSpec::None);
+        assert_eq!("pattern".parse::<SourceSpec>().unwrap(), SourceSpec::Pattern);
+        assert_eq!(
+            "avatar:me.png".parse::<SourceSpec>().unwrap(),
+            SourceSpec::Avatar(PathBuf::from("me.png"))
+        );
+        assert_eq!(
+            "pty:htop -d 10".parse::<SourceSpec>().unwrap(),
+            SourceSpec::Terminal("htop -d 10".to_string())
+        );
+        assert!("scanner".parse::<SourceSpec>().is_err());
+        assert_eq!(
+            "file:clips/Demo.mp4".parse::<SourceSpec>().unwrap(),
+            SourceSpec::File(Playback::new("clips/Demo.mp4"))
+        );
+
+        let source = SourceSpec::None.start(&WebcamConfig::default()).unwrap();
+        assert!(source.try_recv().is_none());
+        assert!(source.commands().is_none());
+    }
+}
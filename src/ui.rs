@@ -1,5 +1,10 @@
+use crate::ascii;
 use crate::client::TermIOClient;
+use crate::color::ColorDepth;
+use crate::effects::{EffectKind, Effects};
+use crate::graphics::{self, Graphics, ImageLayer};
 use crate::message::AsciiFrame;
+use crate::palette::Palette;
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEvent};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
@@ -14,15 +19,43 @@ pub struct TermioUI {
     input_buffer: String,
     scroll_position: u16,
     should_exit: bool,
+    /// Palettes cycled through with Tab, starting with the configured one
+    palettes: Vec<Palette>,
+    /// Palette picked with Tab, drawn by the local renderer; our own stream
+    /// switches to it only if its source takes commands
+    palette_index: usize,
+    effects: Effects,
+    /// Users whose tiles were drawn last, left to right
+    tiles: Vec<String>,
+    /// Tile that F1-F3 toggle effects on, chosen with Left/Right
+    selected_tile: usize,
+    /// Colors the terminal can show, frames are converted down to it
+    color_depth: ColorDepth,
+    /// Pixel images of users' video, for terminals with a graphics protocol
+    images: Option<ImageLayer>,
 }
 
 impl TermioUI {
-    pub fn new(client: TermIOClient) -> Self {
+    pub fn new(client: TermIOClient, color_depth: ColorDepth, graphics: Option<Graphics>) -> Self {
+        let current = client.webcam_config.convert.palette.clone();
+        let mut palettes: Vec<Palette> = Palette::builtins()
+            .into_iter()
+            .filter(|p| p.name != current.name)
+            .collect();
+        palettes.insert(0, current);
+
         Self {
             client,
             input_buffer: String::new(),
             scroll_position: 0,
             should_exit: false,
+            palettes,
+            palette_index: 0,
+            effects: Effects::default(),
+            tiles: Vec::new(),
+            selected_tile: 0,
+            color_depth,
+            images: graphics.map(|g| ImageLayer::new(g, graphics::cell_size())),
         }
     }
 
@@ -41,6 +74,11 @@ impl TermioUI {
         let result = loop {
             // Draw UI
             term.draw(|f| self.draw(f))?;
+            if let Some(images) = self.images.as_mut() {
+                if images.flush(term.backend_mut())? {
+                    term.clear()?;
+                }
+            }
 
             // Handle input with timeout
             if crossterm::event::poll(Duration::from_millis(50))? {
@@ -55,6 +93,9 @@ impl TermioUI {
         };
 
         // Restore terminal
+        if let Some(images) = self.images.as_mut() {
+            images.clear(term.backend_mut())?;
+        }
         disable_raw_mode()?;
         term.clear()?;
 
@@ -62,8 +103,9 @@ impl TermioUI {
     }
 
     /// Draw the UI
-    fn draw(&self, f: &mut Frame) {
+    fn draw(&mut self, f: &mut Frame) {
         let size = f.area();
+        self.effects.tick();
 
         // Main layout: top for video, bottom for chat
         let chunks = Layout::default()
@@ -77,9 +119,11 @@ impl TermioUI {
     }
 
     /// Draw the video display area
-    fn draw_video_area(&self, f: &mut Frame, area: Rect) {
+    fn draw_video_area(&mut self, f: &mut Frame, area: Rect) {
         // Layout for multiple users
-        let frames = self.client.last_frames.read().clone();
+        let mut frames = self.client.last_frames.read().clone();
+        let left = std::mem::take(&mut *self.client.left_frames.write());
+        self.effects.sync(&mut frames, left);
         let users = self.client.connected_users.read().clone();
 
         // Create a title showing connected users
@@ -93,40 +137,71 @@ impl TermioUI {
             let paragraph = Paragraph::new("Waiting for frames...").block(block);
             f.render_widget(paragraph, area);
         } else {
-            // Display each user's frame
+            // Display each user's frame, in a stable order so the selection holds
             let frame_count = frames.len().min(2); // Display up to 2 frames side by side
             let columns = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints(vec![Constraint::Percentage(50); frame_count])
                 .split(area);
 
-            for (idx, (username, frame)) in frames.iter().enumerate() {
-                if idx >= frame_count {
-                    break;
-                }
-                self.render_frame(f, columns[idx], username, frame);
+            let mut tiles: Vec<(&String, &AsciiFrame)> = frames.iter().collect();
+            tiles.sort_by(|a, b| a.0.cmp(b.0));
+            tiles.truncate(frame_count);
+            self.tiles = tiles.iter().map(|(username, _)| username.to_string()).collect();
+            self.selected_tile = self.selected_tile.min(frame_count - 1);
+
+            for (idx, (username, frame)) in tiles.into_iter().enumerate() {
+                self.render_frame(f, columns[idx], username, frame, idx == self.selected_tile);
             }
         }
     }
 
     /// Render a single ASCII frame
-    fn render_frame(&self, f: &mut Frame, area: Rect, username: &str, frame: &AsciiFrame) {
+    fn render_frame(&mut self, f: &mut Frame, area: Rect, username: &str, frame: &AsciiFrame, selected: bool) {
         // Scale the frame to fit the area, preserving aspect ratio
         let width = area.width as usize;
         let height = area.height as usize - 3; // Leave room for border
 
+        // With a graphics protocol the tile is left blank and drawn as an image
+        let pixels = match (&self.images, self.client.render) {
+            (None, None) => None,
+            _ => self.client.last_pixels.read().get(username).cloned(),
+        };
+
+        // Otherwise a renderer picked by this viewer may redraw the pixels as cells
+        let rendered = match (&self.images, self.client.render, &pixels) {
+            (None, Some(render), Some(pixels)) => {
+                let options = render.options(self.palettes[self.palette_index].clone());
+                Some(ascii::render_pixels(pixels, frame.width, frame.height, &options))
+            }
+            _ => None,
+        };
+        let frame = rendered.as_ref().unwrap_or(frame);
+        let image = pixels.filter(|_| self.images.is_some());
+
         // Create the ASCII art text with colors
         let mut text: Vec<Line> = Vec::new();
 
         // Display the frame row by row
-        for y in 0..frame.height.min(height as u16) {
+        let rows = if image.is_some() { 0 } else { frame.height.min(height as u16) };
+        for y in 0..rows {
             let mut line_spans: Vec<Span> = Vec::new();
 
             for x in 0..frame.width.min(width as u16) {
                 if let Some((ch, r, g, b)) = frame.get_cell(x, y) {
+                    // Blank cells, such as keyed out background, stay transparent
+                    if ch == '\0' {
+                        line_spans.push(Span::raw(" "));
+                        continue;
+                    }
+                    let ch = if ch.is_control() { ' ' } else { ch };
+
                     // Create colored span for each character
-                    let color = Color::Rgb(r, g, b);
-                    let span = Span::styled(ch.to_string(), Style::default().fg(color));
+                    let mut style = Style::default().fg(Color::Rgb(r, g, b));
+                    if let Some((br, bg, bb)) = frame.get_background(x, y) {
+                        style = style.bg(Color::Rgb(br, bg, bb));
+                    }
+                    let span = Span::styled(ch.to_string(), style);
                     line_spans.push(span);
                 }
             }
@@ -136,12 +211,25 @@ impl TermioUI {
 
         // Create block with username - highlight if it's you
         let is_self = username == &self.client.username;
-        let title = if is_self {
-            format!("📹 {} (You)", username)
+        let mut title = if is_self {
+            format!(
+                "📹 {} (You) · {} palette, Tab to switch · privacy {}, F4",
+                username,
+                self.client.webcam_config.convert.palette.name,
+                self.client.webcam_config.privacy.name()
+            )
         } else {
             format!("📹 {}", username)
         };
-        
+        let enabled = self.effects.enabled(username);
+        if !enabled.is_empty() {
+            let names: Vec<&str> = enabled.iter().map(|kind| kind.name()).collect();
+            title = format!("{} · {}", title, names.join("+"));
+        }
+        if selected {
+            title = format!("▶ {}", title);
+        }
+
         let block = Block::default()
             .title(title)
             .borders(Borders::ALL)
@@ -151,12 +239,20 @@ impl TermioUI {
             } else {
                 Style::default().fg(Color::Blue)
             });
+        let inner = block.inner(area);
 
         let paragraph = Paragraph::new(text)
             .block(block)
             .scroll((self.scroll_position, 0));
 
         f.render_widget(paragraph, area);
+        match (self.images.as_mut(), image) {
+            (Some(images), Some(pixels)) => images.place(inner, pixels),
+            _ => {
+                self.effects.render(username, f.buffer_mut(), inner);
+                self.color_depth.downgrade(f.buffer_mut(), inner);
+            }
+        }
     }
 
     /// Draw the chat area
@@ -202,7 +298,7 @@ impl TermioUI {
         let input_block = Block::default()
             .borders(Borders::ALL)
             .border_type(BorderType::Plain)
-            .title("Type message (Enter to send, Esc/q to quit)");
+            .title("Type message (Enter to send, Esc/q to quit, F1-F3 tile effects, F4 privacy)");
 
         let input = Paragraph::new(self.input_buffer.as_str())
             .block(input_block)
@@ -226,6 +322,31 @@ impl TermioUI {
             KeyCode::Backspace => {
                 self.input_buffer.pop();
             }
+            KeyCode::Tab => {
+                self.palette_index = (self.palette_index + 1) % self.palettes.len();
+                let palette = self.palettes[self.palette_index].clone();
+                if let Err(e) = self.client.set_palette(palette) {
+                    tracing::warn!("Failed to switch palette: {}", e);
+                }
+            }
+            KeyCode::F(4) => {
+                let mode = self.client.webcam_config.privacy.next();
+                if let Err(e) = self.client.set_privacy(mode) {
+                    tracing::warn!("Failed to switch privacy mode: {}", e);
+                }
+            }
+            KeyCode::F(n @ 1..=3) => {
+                let kind = EffectKind::ALL[n as usize - 1];
+                if let Some(username) = self.tiles.get(self.selected_tile) {
+                    self.effects.toggle(username, kind);
+                }
+            }
+            KeyCode::Left => {
+                self.selected_tile = self.selected_tile.saturating_sub(1);
+            }
+            KeyCode::Right if self.selected_tile + 1 < self.tiles.len() => {
+                self.selected_tile += 1;
+            }
             KeyCode::Char(c) => {
                 self.input_buffer.push(c);
             }
@@ -1,4 +1,4 @@
-use crate::message::{AsciiFrame, UserInfo};
+use crate::message::{AsciiFrame, FrameDelta, PixelFormat, UserInfo};
 use chrono::Utc;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -10,7 +10,10 @@ pub struct User {
     pub id: String,
     pub username: String,
     pub connected_at: String,
-    pub last_frame: Arc<RwLock<Option<AsciiFrame>>>,
+    /// Latest full frame with its sequence number, rebuilt from deltas
+    pub last_frame: Arc<RwLock<Option<(u32, AsciiFrame)>>>,
+    /// Pixel frames the user's client wants, if any
+    pub pixels: Option<PixelFormat>,
 }
 
 impl User {
@@ -21,6 +24,7 @@ impl User {
             username,
             connected_at: Utc::now().to_rfc3339(),
             last_frame: Arc::new(RwLock::new(None)),
+            pixels: None,
         }
     }
 
@@ -34,12 +38,32 @@ impl User {
     }
 
     /// Update the user's latest frame
-    pub async fn update_frame(&self, frame: AsciiFrame) {
-        *self.last_frame.write().await = Some(frame);
+    pub async fn update_frame(&self, frame: AsciiFrame, seq: u32) {
+        *self.last_frame.write().await = Some((seq, frame));
+    }
+
+    /// Apply a delta to the latest frame; false if it is not based on it
+    pub async fn apply_delta(&self, delta: &FrameDelta) -> bool {
+        let mut guard = self.last_frame.write().await;
+        match guard.as_mut() {
+            Some((seq, frame)) if *seq == delta.base_seq => {
+                if delta.apply(frame).is_err() {
+                    return false;
+                }
+                *seq = delta.seq;
+                true
+            }
+            _ => false,
+        }
     }
 
     /// Get the latest frame
     pub async fn get_frame(&self) -> Option<AsciiFrame> {
+        self.last_frame.read().await.as_ref().map(|(_, frame)| frame.clone())
+    }
+
+    /// Get the latest frame with its sequence number
+    pub async fn get_keyframe(&self) -> Option<(u32, AsciiFrame)> {
         self.last_frame.read().await.clone()
     }
 }
@@ -110,6 +134,18 @@ impl UserManager {
             .collect()
     }
 
+    /// Record which pixel frames a user wants
+    pub async fn set_pixels(&self, user_id: &str, pixels: Option<PixelFormat>) {
+        if let Some(user) = self.users.write().await.iter_mut().find(|u| u.id == user_id) {
+            user.pixels = pixels;
+        }
+    }
+
+    /// Richest pixel format any connected user wants, `None` when nobody does
+    pub async fn wants_pixels(&self) -> Option<PixelFormat> {
+        self.users.read().await.iter().filter_map(|u| u.pixels).max()
+    }
+
     /// Check if user exists
     pub async fn user_exists(&self, user_id: &str) -> bool {
         self.users.read().await.iter().any(|u| u.id == user_id)
@@ -134,6 +170,7 @@ impl Clone for User {
             username: self.username.clone(),
             connected_at: self.connected_at.clone(),
             last_frame: Arc::clone(&self.last_frame),
+            pixels: self.pixels,
         }
     }
 }
@@ -0,0 +1,587 @@
+use crate::color::xterm_rgb;
+use crate::message::{AsciiFrame, CellFormat};
+
+/// Colors of cells that set none, as xterm draws them
+const DEFAULT_FG: (u8, u8, u8) = (229, 229, 229);
+const DEFAULT_BG: (u8, u8, u8) = (0, 0, 0);
+
+/// Tab stops are every 8 columns
+const TAB_WIDTH: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Color {
+    #[default]
+    Default,
+    /// Entry of the xterm 256 color palette
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Graphic rendition set by SGR sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Pen {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    reverse: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    ch: char,
+    pen: Pen,
+}
+
+impl Cell {
+    /// Empty cell as erased with `pen`, which keeps only its background
+    fn blank(pen: Pen) -> Self {
+        Self {
+            ch: ' ',
+            pen: Pen {
+                bg: pen.bg,
+                ..Pen::default()
+            },
+        }
+    }
+}
+
+/// Where the parser is within an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// Control sequence, `ESC [`
+    Csi,
+    /// Operating system command `ESC ]`, device control string `ESC P`,
+    /// application program command `ESC _` or privacy message `ESC ^`,
+    /// ignored up to its terminator
+    Str,
+    StrEscape,
+    /// Character set designation, whose one byte argument is ignored
+    Charset,
+}
+
+/// Screen of a terminal program, kept up to date from its output
+///
+/// Understands the subset of xterm that full screen programs such as `htop`,
+/// editors and shells rely on: cursor movement, erasing, scroll regions,
+/// insert and delete, 16, 256 and true color SGR, and the alternate screen.
+/// Anything else is parsed and dropped. Characters are one cell wide.
+pub struct Screen {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    /// Main screen, kept while the alternate screen is shown
+    primary: Option<Vec<Cell>>,
+    cursor: (u16, u16),
+    saved: ((u16, u16), Pen),
+    cursor_visible: bool,
+    pen: Pen,
+    /// Rows scrolled by line feeds, inclusive
+    top: u16,
+    bottom: u16,
+    /// The last column was written, the next character goes on the next line
+    wrap_pending: bool,
+    state: State,
+    /// Parameter bytes of the control sequence being read
+    params: String,
+    /// Bytes of an incomplete UTF-8 character
+    utf8: Vec<u8>,
+    /// Answers to status queries, to be written back to the program
+    replies: Vec<u8>,
+    dirty: bool,
+}
+
+impl Screen {
+    pub fn new(width: u16, height: u16) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        Self {
+            width,
+            height,
+            cells: vec![Cell::blank(Pen::default()); width as usize * height as usize],
+            primary: None,
+            cursor: (0, 0),
+            saved: ((0, 0), Pen::default()),
+            cursor_visible: true,
+            pen: Pen::default(),
+            top: 0,
+            bottom: height - 1,
+            wrap_pending: false,
+            state: State::Ground,
+            params: String::new(),
+            utf8: Vec::new(),
+            replies: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    /// Whether the screen changed since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Answers to the program's queries since the last call
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// Process output of the program
+    pub fn feed(&mut self, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            self.dirty = true;
+        }
+        for &byte in bytes {
+            match self.state {
+                State::Ground => self.ground(byte),
+                State::Escape => self.escape(byte),
+                State::Csi => match byte {
+                    0x30..=0x3f => self.params.push(byte as char),
+                    // Intermediate bytes only appear in sequences we ignore
+                    0x20..=0x2f => {}
+                    0x40..=0x7e => {
+                        self.state = State::Ground;
+                        self.csi(byte as char);
+                    }
+                    0x1b => self.state = State::Escape,
+                    0x18 | 0x1a => self.state = State::Ground,
+                    _ => self.control(byte),
+                },
+                State::Str => match byte {
+                    0x07 => self.state = State::Ground,
+                    0x1b => self.state = State::StrEscape,
+                    _ => {}
+                },
+                State::StrEscape => self.state = State::Ground,
+                State::Charset => self.state = State::Ground,
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8) {
+        // A sequence cut short by anything but a continuation byte is shown
+        // as invalid, and the byte that cut it is handled on its own
+        if !self.utf8.is_empty() && !(0x80..=0xbf).contains(&byte) {
+            self.utf8.clear();
+            self.print(char::REPLACEMENT_CHARACTER);
+        }
+        if !self.utf8.is_empty() || byte >= 0x80 {
+            self.utf8.push(byte);
+            let len = match self.utf8[0] {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            if self.utf8.len() >= len {
+                let ch = std::str::from_utf8(&self.utf8)
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                self.utf8.clear();
+                self.print(ch);
+            }
+            return;
+        }
+        match byte {
+            0x1b => self.state = State::Escape,
+            0x20..=0x7e => self.print(byte as char),
+            _ => self.control(byte),
+        }
+    }
+
+    /// C0 control characters
+    fn control(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.cursor.0 = self.cursor.0.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            0x09 => {
+                let next = (self.cursor.0 / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.cursor.0 = next.min(self.width - 1);
+            }
+            0x0a..=0x0c => self.line_feed(),
+            0x0d => {
+                self.cursor.0 = 0;
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        self.state = State::Ground;
+        match byte {
+            b'[' => {
+                self.params.clear();
+                self.state = State::Csi;
+            }
+            // Sixel and kitty graphics come in these; their payload is never text
+            b']' | b'P' | b'_' | b'^' => self.state = State::Str,
+            b'(' | b')' | b'*' | b'+' => self.state = State::Charset,
+            b'7' => self.saved = (self.cursor, self.pen),
+            b'8' => self.restore_cursor(),
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.cursor.0 = 0;
+                self.line_feed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => *self = Self::new(self.width, self.height),
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.wrap_pending {
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+        let (x, y) = self.cursor;
+        let index = self.index(x, y);
+        self.cells[index] = Cell { ch, pen: self.pen };
+        if x + 1 < self.width {
+            self.cursor.0 += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.1 == self.bottom {
+            self.scroll_up(self.top, 1);
+        } else if self.cursor.1 + 1 < self.height {
+            self.cursor.1 += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.1 == self.top {
+            self.scroll_down(self.top, 1);
+        } else {
+            self.cursor.1 = self.cursor.1.saturating_sub(1);
+        }
+    }
+
+    /// Move rows `from..=bottom` up by `n`, blanking the rows freed at the bottom
+    fn scroll_up(&mut self, from: u16, n: u16) {
+        let width = self.width as usize;
+        let rows = from as usize..self.bottom as usize + 1;
+        let n = (n as usize).min(rows.len());
+        let region = &mut self.cells[rows.start * width..rows.end * width];
+        region.rotate_left(n * width);
+        let len = region.len();
+        region[len - n * width..].fill(Cell::blank(self.pen));
+    }
+
+    /// Move rows `from..=bottom` down by `n`, blanking the rows freed at the top
+    fn scroll_down(&mut self, from: u16, n: u16) {
+        let width = self.width as usize;
+        let rows = from as usize..self.bottom as usize + 1;
+        let n = (n as usize).min(rows.len());
+        let region = &mut self.cells[rows.start * width..rows.end * width];
+        region.rotate_right(n * width);
+        region[..n * width].fill(Cell::blank(self.pen));
+    }
+
+    /// Blank the cells from `start` up to, not including, `end`
+    fn erase(&mut self, start: usize, end: usize) {
+        let blank = Cell::blank(self.pen);
+        let end = end.min(self.cells.len());
+        self.cells[start..end].fill(blank);
+    }
+
+    fn restore_cursor(&mut self) {
+        let ((x, y), pen) = self.saved;
+        self.cursor = (x.min(self.width - 1), y.min(self.height - 1));
+        self.pen = pen;
+        self.wrap_pending = false;
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16) {
+        self.cursor = (x.min(self.width - 1), y.min(self.height - 1));
+        self.wrap_pending = false;
+    }
+
+    /// Switch to or from the alternate screen, which full screen programs draw on
+    fn alternate_screen(&mut self, on: bool) {
+        if on && self.primary.is_none() {
+            let blank = vec![Cell::blank(Pen::default()); self.cells.len()];
+            self.primary = Some(std::mem::replace(&mut self.cells, blank));
+        } else if !on {
+            if let Some(primary) = self.primary.take() {
+                self.cells = primary;
+            }
+        }
+    }
+
+    /// Dispatch a complete control sequence
+    fn csi(&mut self, action: char) {
+        let private = self.params.starts_with('?');
+        let params: Vec<u16> = self
+            .params
+            .trim_start_matches(['?', '>', '='])
+            .split([';', ':'])
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        // Missing and zero parameters mean the default, usually 1
+        let arg = |i: usize, default: u16| match params.get(i) {
+            Some(&p) if p > 0 => p,
+            _ => default,
+        };
+        let (x, y) = self.cursor;
+        let (width, height) = (self.width, self.height);
+
+        match action {
+            'A' => self.set_cursor(x, y.saturating_sub(arg(0, 1))),
+            'B' | 'e' => self.set_cursor(x, y.saturating_add(arg(0, 1))),
+            'C' | 'a' => self.set_cursor(x.saturating_add(arg(0, 1)), y),
+            'D' => self.set_cursor(x.saturating_sub(arg(0, 1)), y),
+            'E' => self.set_cursor(0, y.saturating_add(arg(0, 1))),
+            'F' => self.set_cursor(0, y.saturating_sub(arg(0, 1))),
+            'G' | '`' => self.set_cursor(arg(0, 1) - 1, y),
+            'd' => self.set_cursor(x, arg(0, 1) - 1),
+            'H' | 'f' => self.set_cursor(arg(1, 1) - 1, arg(0, 1) - 1),
+            'J' => {
+                let cursor = self.index(x, y);
+                match params.first().copied().unwrap_or(0) {
+                    0 => self.erase(cursor, self.cells.len()),
+                    1 => self.erase(0, cursor + 1),
+                    _ => self.erase(0, self.cells.len()),
+                }
+            }
+            'K' => {
+                let (line, cursor) = (self.index(0, y), self.index(x, y));
+                match params.first().copied().unwrap_or(0) {
+                    0 => self.erase(cursor, line + width as usize),
+                    1 => self.erase(line, cursor + 1),
+                    _ => self.erase(line, line + width as usize),
+                }
+            }
+            'X' => {
+                let cursor = self.index(x, y);
+                let end = self.index(0, y) + width as usize;
+                self.erase(cursor, (cursor + arg(0, 1) as usize).min(end));
+            }
+            '@' | 'P' => {
+                let line = self.index(0, y);
+                let row = &mut self.cells[line + x as usize..line + width as usize];
+                let n = (arg(0, 1) as usize).min(row.len());
+                if action == '@' {
+                    row.rotate_right(n);
+                    row[..n].fill(Cell::blank(self.pen));
+                } else {
+                    row.rotate_left(n);
+                    let len = row.len();
+                    row[len - n..].fill(Cell::blank(self.pen));
+                }
+            }
+            'L' if (self.top..=self.bottom).contains(&y) => {
+                self.scroll_down(y, arg(0, 1));
+                self.cursor.0 = 0;
+            }
+            'M' if (self.top..=self.bottom).contains(&y) => {
+                self.scroll_up(y, arg(0, 1));
+                self.cursor.0 = 0;
+            }
+            'S' => self.scroll_up(self.top, arg(0, 1)),
+            'T' => self.scroll_down(self.top, arg(0, 1)),
+            'r' => {
+                let top = arg(0, 1) - 1;
+                let bottom = arg(1, height).min(height) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.set_cursor(0, 0);
+                }
+            }
+            's' => self.saved = (self.cursor, self.pen),
+            'u' => self.restore_cursor(),
+            'm' => self.sgr(&params),
+            'h' | 'l' if private => {
+                let on = action == 'h';
+                for &mode in &params {
+                    match mode {
+                        25 => self.cursor_visible = on,
+                        47 | 1047 => self.alternate_screen(on),
+                        1049 => {
+                            // Also saves the cursor on entry and restores it on exit
+                            if on {
+                                self.saved = (self.cursor, self.pen);
+                                self.alternate_screen(true);
+                            } else {
+                                self.alternate_screen(false);
+                                self.restore_cursor();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            'n' => match arg(0, 0) {
+                5 => self.replies.extend_from_slice(b"\x1b[0n"),
+                6 => self.replies.extend(format!("\x1b[{};{}R", y + 1, x + 1).bytes()),
+                _ => {}
+            },
+            // Primary device attributes: a VT100 with advanced video
+            'c' if !self.params.starts_with('>') && arg(0, 0) == 0 => {
+                self.replies.extend_from_slice(b"\x1b[?1;2c");
+            }
+            _ => {}
+        }
+    }
+
+    /// Select graphic rendition
+    fn sgr(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+        while let Some(p) = params.next() {
+            match p {
+                0 => self.pen = Pen::default(),
+                1 => self.pen.bold = true,
+                22 => self.pen.bold = false,
+                7 => self.pen.reverse = true,
+                27 => self.pen.reverse = false,
+                30..=37 => self.pen.fg = Color::Indexed(p as u8 - 30),
+                40..=47 => self.pen.bg = Color::Indexed(p as u8 - 40),
+                90..=97 => self.pen.fg = Color::Indexed(p as u8 - 90 + 8),
+                100..=107 => self.pen.bg = Color::Indexed(p as u8 - 100 + 8),
+                39 => self.pen.fg = Color::Default,
+                49 => self.pen.bg = Color::Default,
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(|i| Color::Indexed(i as u8)),
+                        Some(2) => {
+                            let mut channel = || params.next().unwrap_or(0).min(255) as u8;
+                            Some(Color::Rgb(channel(), channel(), channel()))
+                        }
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        if p == 38 {
+                            self.pen.fg = color;
+                        } else {
+                            self.pen.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The screen as cells with foreground and background colors
+    ///
+    /// The cursor, while shown, is drawn as a reversed cell.
+    pub fn to_frame(&self) -> AsciiFrame {
+        let format = CellFormat {
+            unicode: false,
+            background: true,
+        };
+        let mut frame = AsciiFrame::with_format(self.width, self.height, format);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = self.cells[self.index(x, y)];
+                let fg = match cell.pen.fg {
+                    Color::Default => DEFAULT_FG,
+                    // Bold brightens the first 8 colors, as in most terminals
+                    Color::Indexed(i) if cell.pen.bold && i < 8 => xterm_rgb(i + 8),
+                    Color::Indexed(i) => xterm_rgb(i),
+                    Color::Rgb(r, g, b) => (r, g, b),
+                };
+                let bg = match cell.pen.bg {
+                    Color::Default => DEFAULT_BG,
+                    Color::Indexed(i) => xterm_rgb(i),
+                    Color::Rgb(r, g, b) => (r, g, b),
+                };
+                let cursor = self.cursor_visible && (x, y) == self.cursor;
+                let ((fr, fg, fb), (br, bg, bb)) = if cell.pen.reverse != cursor { (bg, fg) } else { (fg, bg) };
+                frame.set_cell(x, y, cell.ch, fr, fg, fb);
+                frame.set_background(x, y, br, bg, bb);
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(frame: &AsciiFrame, y: u16) -> String {
+        (0..frame.width).map(|x| frame.get_cell(x, y).unwrap().0).collect()
+    }
+
+    #[test]
+    fn test_text_and_colors() {
+        let mut screen = Screen::new(12, 3);
+        screen.feed(b"\x1b[?25l\x1b[31mred\x1b[0m \x1b[1;32mok\x1b[m\r\n");
+        screen.feed("\x1b[38;5;208m\x1b[48;2;10;20;30m\u{2588}\x1b[7mx".as_bytes());
+        let frame = screen.to_frame();
+
+        assert_eq!(text(&frame, 0), "red ok      ");
+        assert_eq!(frame.get_cell(0, 0), Some(('r', 205, 0, 0)));
+        assert_eq!(frame.get_cell(3, 0), Some((' ', 229, 229, 229)));
+        // Bold green is the bright green
+        assert_eq!(frame.get_cell(4, 0), Some(('o', 0, 255, 0)));
+        assert_eq!(frame.get_cell(0, 1), Some(('\u{2588}', 255, 135, 0)));
+        assert_eq!(frame.get_background(0, 1), Some((10, 20, 30)));
+        assert_eq!(frame.get_cell(1, 1), Some(('x', 10, 20, 30)));
+        assert_eq!(frame.get_background(1, 1), Some((255, 135, 0)));
+    }
+
+    #[test]
+    fn test_cursor_erase_and_scroll() {
+        let mut screen = Screen::new(5, 3);
+        screen.feed(b"\x1b[?25labcdefg\r\nhi\x1b[1;3HX\x1b[2;1H\x1b[K");
+        let frame = screen.to_frame();
+        // Long lines wrap, positions are 1-based, erasing clears to the end
+        assert_eq!(text(&frame, 0), "abXde");
+        assert_eq!(text(&frame, 1), "     ");
+        assert_eq!(text(&frame, 2), "hi   ");
+
+        // A line feed on the last row scrolls, inside the scroll region only
+        screen.feed(b"\x1b[2;3r\x1b[3;1Hnew\n");
+        let frame = screen.to_frame();
+        assert_eq!(text(&frame, 0), "abXde");
+        assert_eq!(text(&frame, 1), "new  ");
+        assert_eq!(text(&frame, 2), "     ");
+
+        // The alternate screen is cleared and the main one comes back after
+        screen.feed(b"\x1b[?1049h\x1b[2JTOP\x1b[?1049l");
+        assert_eq!(text(&screen.to_frame(), 0), "abXde");
+    }
+
+    #[test]
+    fn test_replies_to_queries() {
+        let mut screen = Screen::new(10, 5);
+        screen.feed(b"\x1b[3;4H\x1b[6n\x1b[c\x1b]0;title\x07");
+        assert_eq!(screen.take_replies(), b"\x1b[3;4R\x1b[?1;2c");
+        assert!(screen.take_dirty());
+        assert!(!screen.take_dirty());
+    }
+
+    #[test]
+    fn test_strings_are_skipped() {
+        let mut screen = Screen::new(6, 1);
+        // A sixel image, a kitty graphics command, a privacy message and a title
+        screen.feed(b"\x1b[?25la\x1bPq#0;2;0;0;0#0~~-\x1b\\b\x1b_Gf=24,s=1;AAAA\x1b\\c");
+        screen.feed(b"\x1b^secret\x1b\\d\x1b]2;title\x1b\\e");
+        assert_eq!(text(&screen.to_frame(), 0), "abcde ");
+    }
+
+    #[test]
+    fn test_truncated_utf8() {
+        let mut screen = Screen::new(4, 1);
+        screen.feed(b"\x1b[?25l\xc3\x1b[31mx\xc3\xa9");
+        let frame = screen.to_frame();
+        // The escape ending the sequence still starts a control sequence
+        assert_eq!(text(&frame, 0), "\u{fffd}x\u{e9} ");
+        assert_eq!(frame.get_cell(1, 0), Some(('x', 205, 0, 0)));
+    }
+}
@@ -1,15 +1,29 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use crossbeam_channel::{bounded, Receiver, Sender};
 use ffmpeg_next::codec;
 use ffmpeg_next::format::Pixel;
 use ffmpeg_next::media::Type;
 use ffmpeg_next::software::scaling::Flags;
 use ffmpeg_next::util::frame::Video;
+use std::path::PathBuf;
 use std::thread;
+use std::time::{Duration, Instant};
 use std::ffi::{CStr, CString};
 
-use crate::ascii;
-use crate::message::AsciiFrame;
+use crate::ascii::ConvertOptions;
+use crate::aspect::{self, Fit, DEFAULT_CELL_ASPECT};
+use crate::chroma::{ChromaKey, ChromaKeyer};
+use crate::exposure::{AutoExposure, Exposure};
+use crate::filter::{FilterChain, FilterSpec};
+use crate::image::RgbImage;
+use crate::message::{AsciiFrame, PixelFormat, PixelFrame};
+use crate::palette::Palette;
+use crate::pipeline::FramePipeline;
+use crate::privacy::PrivacyMode;
+use crate::source::FrameSource;
+
+/// Frame rate of generated sources when the config leaves it uncapped
+pub const DEFAULT_FPS: u32 = 30;
 
 /// Configuration for webcam capture
 #[derive(Debug, Clone)]
@@ -22,8 +36,25 @@ pub struct WebcamConfig {
     pub height: u16,
     /// FPS cap (0 = uncapped)
     pub fps_cap: u32,
-    /// Monochrome mode
-    pub mono: bool,
+    /// Brightness correction applied before conversion
+    pub exposure: Exposure,
+    /// Filters applied in order to every captured frame
+    pub filters: Vec<FilterSpec>,
+    /// Temporal denoising strength, 0.0 (off) to 1.0
+    pub denoise: f32,
+    /// Green screen keying, applied after the filters
+    pub chroma_key: Option<ChromaKey>,
+    /// Hiding of the static background, applied last before conversion
+    pub privacy: PrivacyMode,
+    /// How the camera picture is fitted into the frame
+    pub fit: Fit,
+    /// Height of a viewer's terminal cell divided by its width
+    pub cell_aspect: f32,
+    /// How pixels are turned into cells
+    pub convert: ConvertOptions,
+    /// Size in pixels of the images sent to viewers that draw pixel graphics
+    pub pixel_width: u16,
+    pub pixel_height: u16,
 }
 
 impl Default for WebcamConfig {
@@ -33,37 +64,132 @@ impl Default for WebcamConfig {
             width: 80,
             height: 24,
             fps_cap: 30,
-            mono: false,
+            exposure: Exposure::Off,
+            filters: Vec::new(),
+            denoise: 0.0,
+            chroma_key: None,
+            privacy: PrivacyMode::Off,
+            fit: Fit::default(),
+            cell_aspect: DEFAULT_CELL_ASPECT,
+            convert: ConvertOptions::default(),
+            pixel_width: 160,
+            pixel_height: 120,
         }
     }
 }
 
+impl WebcamConfig {
+    /// Filters of the capture pipeline, with the chroma key last
+    pub fn filter_chain(&self) -> Result<FilterChain> {
+        let mut chain = FilterChain::new(&self.filters);
+        if let Some(key) = &self.chroma_key {
+            chain.push(Box::new(ChromaKeyer::new(key.clone())?));
+        }
+        Ok(chain)
+    }
+
+    /// Time between frames of generated sources, at the FPS cap or at
+    /// `DEFAULT_FPS` when uncapped
+    pub fn frame_interval(&self) -> Duration {
+        let fps = if self.fps_cap > 0 { self.fps_cap } else { DEFAULT_FPS };
+        Duration::from_secs(1) / fps
+    }
+}
+
+/// Video file played in place of a camera
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback {
+    pub path: PathBuf,
+    /// Start over at `start` after the last frame
+    pub looping: bool,
+    /// Position to start from, in seconds
+    pub start: f64,
+    /// Playback rate, 1.0 is real time
+    pub speed: f64,
+}
+
+impl Playback {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            looping: false,
+            start: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    /// Check that the start and speed can be played
+    pub fn validate(&self) -> Result<()> {
+        if !(self.speed.is_finite() && SPEED_RANGE.contains(&self.speed)) {
+            bail!(
+                "Playback speed must be between {} and {}, not {}",
+                SPEED_RANGE.start(),
+                SPEED_RANGE.end(),
+                self.speed
+            );
+        }
+        if !(self.start.is_finite() && self.start >= 0.0) {
+            bail!("Start position must be a number of seconds, not {}", self.start);
+        }
+        Ok(())
+    }
+}
+
+/// Playback rates accepted, from a slow motion crawl to a fast skim
+const SPEED_RANGE: std::ops::RangeInclusive<f64> = 0.01..=100.0;
+
+/// Frames this far behind their timestamp are dropped to catch up
+const LATE_FRAME: Duration = Duration::from_millis(100);
+
 /// Webcam capture handler that spawns a background thread
 pub struct WebcamCapture {
     receiver: Receiver<AsciiFrame>,
-    _sender: Sender<WebcamCommand>,
+    pixel_receiver: Receiver<PixelFrame>,
+    commands: Sender<WebcamCommand>,
 }
 
 /// Commands to control the webcam
 pub enum WebcamCommand {
     Stop,
+    /// Switch the glyph palette of the running capture
+    SetPalette(Palette),
+    /// Switch how the static background is hidden
+    SetPrivacy(PrivacyMode),
+    /// Start or stop producing pixel frames next to the cell frames
+    SetPixels(Option<PixelFormat>),
 }
 
 impl WebcamCapture {
     /// Start capturing from webcam with given configuration
     pub fn start(config: WebcamConfig) -> Result<Self> {
+        Self::spawn(config, None)
+    }
+
+    /// Play a video file (or animated GIF) through the same pipeline as the camera
+    pub fn start_file(config: WebcamConfig, playback: Playback) -> Result<Self> {
+        playback.validate()?;
+        Self::spawn(config, Some(playback))
+    }
+
+    fn spawn(config: WebcamConfig, playback: Option<Playback>) -> Result<Self> {
         let (tx, rx) = bounded::<AsciiFrame>(2);
-        let (cmd_tx, cmd_rx) = bounded::<WebcamCommand>(1);
+        let (pixel_tx, pixel_rx) = bounded::<PixelFrame>(2);
+        let (cmd_tx, cmd_rx) = bounded::<WebcamCommand>(4);
 
         thread::spawn(move || {
-            if let Err(e) = Self::capture_loop(&config, &tx, &cmd_rx) {
+            let result = match &playback {
+                Some(playback) => Self::playback_loop(&config, playback, &tx, &pixel_tx, &cmd_rx),
+                None => Self::capture_loop(&config, &tx, &pixel_tx, &cmd_rx),
+            };
+            if let Err(e) = result {
                 eprintln!("Webcam capture error: {}", e);
             }
         });
 
         Ok(Self {
             receiver: rx,
-            _sender: cmd_tx,
+            pixel_receiver: pixel_rx,
+            commands: cmd_tx,
         })
     }
 
@@ -71,6 +197,7 @@ impl WebcamCapture {
     fn capture_loop(
         config: &WebcamConfig,
         tx: &Sender<AsciiFrame>,
+        pixel_tx: &Sender<PixelFrame>,
         cmd_rx: &Receiver<WebcamCommand>,
     ) -> Result<()> {
         ffmpeg_next::init()?;
@@ -153,15 +280,37 @@ impl WebcamCapture {
 
         tracing::info!("Successfully opened webcam device: {}", device_spec);
 
-        Self::process_frames(&mut ictx, &tx, &cmd_rx, config)
+        Self::process_frames(&mut ictx, tx, pixel_tx, cmd_rx, config, None)
+    }
+
+    /// Playback loop for video files - runs in background thread
+    fn playback_loop(
+        config: &WebcamConfig,
+        playback: &Playback,
+        tx: &Sender<AsciiFrame>,
+        pixel_tx: &Sender<PixelFrame>,
+        cmd_rx: &Receiver<WebcamCommand>,
+    ) -> Result<()> {
+        ffmpeg_next::init()?;
+        let mut ictx = ffmpeg_next::format::input(&playback.path)
+            .with_context(|| format!("Failed to open video file {}", playback.path.display()))?;
+        tracing::info!("Playing {} at {}x speed", playback.path.display(), playback.speed);
+
+        Self::process_frames(&mut ictx, tx, pixel_tx, cmd_rx, config, Some(playback))
     }
 
     /// Process frames from the input context
+    ///
+    /// Live devices are paced by `fps_cap`. Files are played at their own
+    /// timestamps instead, with `fps_cap` dropping frames rather than slowing
+    /// playback down.
     fn process_frames(
         ictx: &mut ffmpeg_next::format::context::Input,
         tx: &Sender<AsciiFrame>,
+        pixel_tx: &Sender<PixelFrame>,
         cmd_rx: &Receiver<WebcamCommand>,
         config: &WebcamConfig,
+        playback: Option<&Playback>,
     ) -> Result<()> {
         let video_stream = ictx
             .streams()
@@ -169,6 +318,7 @@ impl WebcamCapture {
             .ok_or_else(|| anyhow!("No video stream found"))?;
 
         let video_stream_idx = video_stream.index();
+        let time_base = f64::from(video_stream.time_base());
 
         let dec_ctx = codec::context::Context::from_parameters(video_stream.parameters())
             .context("Failed to create decoder context")?;
@@ -177,19 +327,28 @@ impl WebcamCapture {
         let src_width = decoder.width() as u32;
         let src_height = decoder.height() as u32;
 
-        // Create scaler to convert to target resolution and RGB24
+        // Create scaler to convert to RGB24 at the pixel resolution of the render
+        // mode, keeping the camera's aspect on cells that are not square
+        let (pixel_width, pixel_height) = config.convert.mode.pixel_size(config.width, config.height);
+        let (content_width, content_height) = aspect::content_size(
+            (src_width, src_height),
+            (pixel_width, pixel_height),
+            (config.width, config.height),
+            config.cell_aspect,
+            config.fit,
+        );
         let mut scaler = ffmpeg_next::software::scaling::Context::get(
             decoder.format(),
             src_width,
             src_height,
             Pixel::RGB24,
-            config.width as u32,
-            config.height as u32,
+            content_width,
+            content_height,
             Flags::BILINEAR,
         ).context("Failed to create scaler")?;
 
         let mut decoded = ffmpeg_next::frame::Video::empty();
-        let mut rgb = Video::new(Pixel::RGB24, config.width as u32, config.height as u32);
+        let mut rgb = Video::new(Pixel::RGB24, content_width, content_height);
 
         // Calculate frame duration for FPS capping
         let frame_duration = if config.fps_cap > 0 {
@@ -199,58 +358,146 @@ impl WebcamCapture {
         };
 
         let mut last_frame_time = std::time::Instant::now();
+        let mut exposure = AutoExposure::new(config.exposure);
+        let mut pipeline = FramePipeline::new(config)?;
+        let mut pixel_scaler: Option<PixelScaler> = None;
+
+        // Wall clock time and file position of the first frame played, and
+        // the position of the last frame sent, for files
+        let mut clock: Option<(Instant, f64)> = None;
+        let mut last_position: Option<f64> = None;
+        let start = playback.map_or(0.0, |p| p.start);
+        if start > 0.0 {
+            Self::seek(ictx, start)?;
+        }
 
-        // Main capture loop
-        for (_stream, packet) in ictx.packets() {
-            // Check for stop command
-            if cmd_rx.try_recv().is_ok() {
-                break;
-            }
-
-            if _stream.index() != video_stream_idx {
-                continue;
-            }
-
-            match decoder.send_packet(&packet) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Send packet error: {}", e);
-                    continue;
+        // Main capture loop, run again from `start` for each loop of a file
+        loop {
+            // A final `None` drains the decoder at the end of the input
+            for next in ictx.packets().map(Some).chain(std::iter::once(None)) {
+                if !pipeline.handle_commands(cmd_rx)? {
+                    return Ok(());
                 }
-            }
-
-            while let Ok(_) = decoder.receive_frame(&mut decoded) {
-                // Apply FPS cap
-                let elapsed = last_frame_time.elapsed();
-                if frame_duration.as_millis() > 0 && elapsed < frame_duration {
-                    let wait_time = frame_duration - elapsed;
-                    thread::sleep(wait_time);
+                // Pixel frames get their own scaler, created while some viewer wants them
+                if pipeline.wants_pixels() != pixel_scaler.is_some() {
+                    pixel_scaler = if pipeline.wants_pixels() {
+                        Some(PixelScaler::new(&decoder, config)?)
+                    } else {
+                        None
+                    };
                 }
 
-                // Scale frame to target resolution
-                scaler.run(&decoded, &mut rgb)?;
-
-                // Convert to ASCII
-                let frame = ascii::to_ascii_frame(&rgb, config.width, config.height, config.mono);
+                match next {
+                    Some((stream, _)) if stream.index() != video_stream_idx => continue,
+                    Some((_, packet)) => {
+                        if let Err(e) = decoder.send_packet(&packet) {
+                            eprintln!("Send packet error: {}", e);
+                            continue;
+                        }
+                    }
+                    // The decoder still holds the last frames, B-frames among them
+                    None => {
+                        let _ = decoder.send_eof();
+                    }
+                }
 
-                // Send frame to receiver (blocking if buffer full)
-                if tx.send(frame).is_err() {
-                    // Receiver dropped, exit
-                    return Ok(());
+                while let Ok(_) = decoder.receive_frame(&mut decoded) {
+                    let position = decoded.timestamp().map(|ts| ts as f64 * time_base);
+                    match (playback, position) {
+                        (Some(playback), Some(position)) => {
+                            // Seeking lands on the keyframe before `start`
+                            if position < start {
+                                continue;
+                            }
+
+                            // Wait for the frame's time, or skip it when far behind
+                            let (started, first) = *clock.get_or_insert((Instant::now(), position));
+                            let due = started + Duration::from_secs_f64(((position - first) / playback.speed).max(0.0));
+                            let now = Instant::now();
+                            if due > now {
+                                thread::sleep(due - now);
+                            } else if now - due > LATE_FRAME {
+                                continue;
+                            }
+
+                            // Frames above the FPS cap are dropped, not delayed. Their
+                            // spacing in the file decides, with 5% slack so a file at
+                            // exactly the cap keeps every frame.
+                            let min_step = frame_duration.as_secs_f64() * playback.speed * 0.95;
+                            if last_position.is_some_and(|last| position - last < min_step) {
+                                continue;
+                            }
+                            last_position = Some(position);
+                        }
+                        _ => {
+                            // Apply FPS cap
+                            let elapsed = last_frame_time.elapsed();
+                            if frame_duration.as_millis() > 0 && elapsed < frame_duration {
+                                let wait_time = frame_duration - elapsed;
+                                thread::sleep(wait_time);
+                            }
+                        }
+                    }
+
+                    // Scale frame to target resolution
+                    scaler.run(&decoded, &mut rgb)?;
+
+                    // Convert to cells, exposing before bars are added or edges cut
+                    let mut image = RgbImage::from_video(&rgb);
+                    exposure.apply(&mut image);
+                    let frame = pipeline.frame(image.centered(pixel_width as usize, pixel_height as usize));
+
+                    // Pixel frames are best effort and dropped when the sender lags
+                    if let Some(scaler) = pixel_scaler.as_mut() {
+                        if let Some(pixels) = pipeline.pixel_frame(scaler.run(&decoded)?) {
+                            let _ = pixel_tx.try_send(pixels);
+                        }
+                    }
+
+                    // Send frame to receiver (blocking if buffer full)
+                    if tx.send(frame).is_err() {
+                        // Receiver dropped, exit
+                        return Ok(());
+                    }
+
+                    last_frame_time = std::time::Instant::now();
                 }
+            }
 
-                last_frame_time = std::time::Instant::now();
+            if !playback.is_some_and(|p| p.looping) {
+                break;
             }
+            Self::seek(ictx, start)?;
+            decoder.flush();
+            clock = None;
+            last_position = None;
         }
 
         Ok(())
     }
 
+    /// Jump to a position in seconds, landing on the keyframe at or before it
+    fn seek(ictx: &mut ffmpeg_next::format::context::Input, seconds: f64) -> Result<()> {
+        let ts = (seconds * f64::from(ffmpeg_next::rescale::TIME_BASE.denominator())) as i64;
+        ictx.seek(ts, ..ts).context("Failed to seek")?;
+        Ok(())
+    }
+
+    /// Sender for controlling the capture thread, usable after the capture is moved
+    pub fn commands(&self) -> Sender<WebcamCommand> {
+        self.commands.clone()
+    }
+
     /// Try to receive a frame without blocking
     pub fn try_recv(&self) -> Option<AsciiFrame> {
         self.receiver.try_recv().ok()
     }
 
+    /// Try to receive a pixel frame without blocking
+    pub fn try_recv_pixels(&self) -> Option<PixelFrame> {
+        self.pixel_receiver.try_recv().ok()
+    }
+
     /// Receive next frame (blocking)
     pub fn recv(&self) -> Result<AsciiFrame> {
         self.receiver
@@ -259,6 +506,66 @@ impl WebcamCapture {
     }
 }
 
+impl FrameSource for WebcamCapture {
+    fn try_recv(&self) -> Option<AsciiFrame> {
+        WebcamCapture::try_recv(self)
+    }
+
+    fn try_recv_pixels(&self) -> Option<PixelFrame> {
+        WebcamCapture::try_recv_pixels(self)
+    }
+
+    fn commands(&self) -> Option<Sender<WebcamCommand>> {
+        Some(WebcamCapture::commands(self))
+    }
+}
+
+/// Scaler and exposure making the images of pixel frames
+struct PixelScaler {
+    scaler: ffmpeg_next::software::scaling::Context,
+    rgb: Video,
+    exposure: AutoExposure,
+    /// Size of the frames sent
+    size: (usize, usize),
+}
+
+impl PixelScaler {
+    fn new(decoder: &codec::decoder::Video, config: &WebcamConfig) -> Result<Self> {
+        // Pixel frames have square pixels, so they are fitted like cells of aspect 1
+        let (width, height) = aspect::content_size(
+            (decoder.width(), decoder.height()),
+            (config.pixel_width as u32, config.pixel_height as u32),
+            (config.pixel_width, config.pixel_height),
+            1.0,
+            config.fit,
+        );
+        let scaler = ffmpeg_next::software::scaling::Context::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            Pixel::RGB24,
+            width,
+            height,
+            Flags::BILINEAR,
+        )
+        .context("Failed to create pixel scaler")?;
+
+        Ok(Self {
+            scaler,
+            rgb: Video::new(Pixel::RGB24, width, height),
+            size: (config.pixel_width as usize, config.pixel_height as usize),
+            exposure: AutoExposure::new(config.exposure),
+        })
+    }
+
+    fn run(&mut self, decoded: &Video) -> Result<RgbImage> {
+        self.scaler.run(decoded, &mut self.rgb)?;
+        let mut image = RgbImage::from_video(&self.rgb);
+        self.exposure.apply(&mut image);
+        Ok(image.centered(self.size.0, self.size.1))
+    }
+}
+
 /// Detect available webcam devices
 pub fn detect_devices() -> Result<Vec<String>> {
     // This is a simplified detection for common platforms
@@ -296,3 +603,26 @@ pub fn detect_devices() -> Result<Vec<String>> {
         Ok(vec!["0".to_string()])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback_validation() {
+        let playback = |start, speed| Playback {
+            start,
+            speed,
+            ..Playback::new("demo.mp4")
+        };
+        assert!(playback(0.0, 1.0).validate().is_ok());
+        assert!(playback(12.5, 0.5).validate().is_ok());
+
+        for speed in [0.0, -1.0, 1e-20, 1e20, f64::NAN, f64::INFINITY] {
+            assert!(playback(0.0, speed).validate().is_err(), "speed {}", speed);
+        }
+        for start in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(playback(start, 1.0).validate().is_err(), "start {}", start);
+        }
+    }
+}
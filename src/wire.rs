@@ -0,0 +1,449 @@
+use crate::compress::{self, Compression};
+use crate::message::{AsciiFrame, CellFormat, CellRun, Encoding, FrameDelta, Message, PixelFormat, PixelFrame};
+use anyhow::{anyhow, bail, Result};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// First byte of every binary message
+const MAGIC: u8 = b'T';
+
+/// Version of the binary framing; bumped on any layout change
+pub const WIRE_VERSION: u8 = 5;
+
+/// Payload kinds carried in the binary header
+const KIND_JSON: u8 = 0;
+const KIND_FRAME: u8 = 1;
+const KIND_DELTA: u8 = 2;
+const KIND_PIXELS: u8 = 3;
+
+/// Encode a message using the binary framing
+///
+/// Layout: `[magic, version, kind, compression]` followed by the payload.
+/// Frames, deltas and pixel frames use a compact layout ending in their cell or
+/// pixel bytes, which are the only part that gets compressed; every other
+/// message is carried as an uncompressed JSON body since they are small and
+/// infrequent.
+pub fn encode(msg: &Message, compression: Compression) -> Result<Vec<u8>> {
+    let mut out = vec![MAGIC, WIRE_VERSION];
+
+    match msg {
+        Message::Frame {
+            user_id,
+            username,
+            frame,
+            seq,
+        } => {
+            out.push(KIND_FRAME);
+            out.push(compression.id());
+            out.push(frame.format.flags());
+            out.extend_from_slice(&seq.to_le_bytes());
+            out.extend_from_slice(&frame.width.to_le_bytes());
+            out.extend_from_slice(&frame.height.to_le_bytes());
+            put_str(&mut out, user_id)?;
+            put_str(&mut out, username)?;
+            out.extend_from_slice(&compress::compress(&frame.data, compression, frame.format.cell_size()));
+        }
+        Message::FrameDelta {
+            user_id,
+            username,
+            delta,
+        } => {
+            out.push(KIND_DELTA);
+            out.push(compression.id());
+            out.push(delta.format.flags());
+            out.extend_from_slice(&delta.seq.to_le_bytes());
+            out.extend_from_slice(&delta.base_seq.to_le_bytes());
+            out.extend_from_slice(&delta.width.to_le_bytes());
+            out.extend_from_slice(&delta.height.to_le_bytes());
+            put_str(&mut out, user_id)?;
+            put_str(&mut out, username)?;
+            out.extend_from_slice(&(delta.runs.len() as u32).to_le_bytes());
+            for run in &delta.runs {
+                out.extend_from_slice(&run.start.to_le_bytes());
+                out.extend_from_slice(&(run.data.len() as u32).to_le_bytes());
+            }
+            // Cell data of all runs goes last so it compresses as one block
+            let cells: Vec<u8> = delta.runs.iter().flat_map(|run| run.data.iter().copied()).collect();
+            out.extend_from_slice(&compress::compress(&cells, compression, delta.format.cell_size()));
+        }
+        Message::PixelFrame {
+            user_id,
+            username,
+            frame,
+        } => {
+            out.push(KIND_PIXELS);
+            out.push(compression.id());
+            out.push(frame.format.id());
+            out.extend_from_slice(&frame.width.to_le_bytes());
+            out.extend_from_slice(&frame.height.to_le_bytes());
+            put_str(&mut out, user_id)?;
+            put_str(&mut out, username)?;
+            out.extend_from_slice(&compress::compress(&frame.data, compression, frame.format.bytes_per_pixel()));
+        }
+        _ => {
+            out.push(KIND_JSON);
+            out.push(Compression::None.id());
+            out.extend_from_slice(&serde_json::to_vec(msg)?);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decode a message produced by `encode`
+pub fn decode(bytes: &[u8]) -> Result<Message> {
+    let mut r = Reader { bytes, pos: 0 };
+
+    if r.u8()? != MAGIC {
+        bail!("Not a TermIO binary message");
+    }
+    let version = r.u8()?;
+    if version != WIRE_VERSION {
+        bail!("Unsupported wire version {} (expected {})", version, WIRE_VERSION);
+    }
+
+    let kind = r.u8()?;
+    let compression = Compression::from_id(r.u8()?)?;
+
+    match kind {
+        KIND_JSON => Ok(serde_json::from_slice(r.rest())?),
+        KIND_FRAME => {
+            let format = CellFormat::from_flags(r.u8()?)?;
+            let seq = r.u32()?;
+            let width = r.u16()?;
+            let height = r.u16()?;
+            let user_id = r.string()?;
+            let username = r.string()?;
+            let expected = width as usize * height as usize * format.cell_size();
+            let data = compress::decompress(r.rest(), compression, format.cell_size(), expected)?;
+
+            if data.len() != expected {
+                bail!("Frame data is {} bytes, expected {}", data.len(), expected);
+            }
+
+            Ok(Message::Frame {
+                user_id,
+                username,
+                frame: AsciiFrame {
+                    width,
+                    height,
+                    data,
+                    format,
+                },
+                seq,
+            })
+        }
+        KIND_DELTA => {
+            let format = CellFormat::from_flags(r.u8()?)?;
+            let seq = r.u32()?;
+            let base_seq = r.u32()?;
+            let width = r.u16()?;
+            let height = r.u16()?;
+            let user_id = r.string()?;
+            let username = r.string()?;
+
+            let count = r.u32()? as usize;
+            let mut spans = Vec::with_capacity(count.min(width as usize * height as usize));
+            for _ in 0..count {
+                spans.push((r.u32()?, r.u32()? as usize));
+            }
+
+            // Runs cover at most the whole frame
+            let frame_bytes = width as usize * height as usize * format.cell_size();
+            let expected = spans.iter().map(|&(_, len)| len).sum::<usize>().min(frame_bytes);
+            let cells = compress::decompress(r.rest(), compression, format.cell_size(), expected)?;
+            let mut offset = 0;
+            let mut runs = Vec::with_capacity(spans.len());
+            for (start, len) in spans {
+                let Some(data) = cells.get(offset..offset + len) else {
+                    bail!("Delta runs exceed cell data");
+                };
+                runs.push(CellRun {
+                    start,
+                    data: data.to_vec(),
+                });
+                offset += len;
+            }
+
+            Ok(Message::FrameDelta {
+                user_id,
+                username,
+                delta: FrameDelta {
+                    seq,
+                    base_seq,
+                    width,
+                    height,
+                    format,
+                    runs,
+                },
+            })
+        }
+        KIND_PIXELS => {
+            let format = PixelFormat::from_id(r.u8()?)?;
+            let width = r.u16()?;
+            let height = r.u16()?;
+            let user_id = r.string()?;
+            let username = r.string()?;
+            let expected = width as usize * height as usize * format.bytes_per_pixel();
+            let data = compress::decompress(r.rest(), compression, format.bytes_per_pixel(), expected)?;
+
+            if data.len() != expected {
+                bail!("Pixel data is {} bytes, expected {}", data.len(), expected);
+            }
+
+            Ok(Message::PixelFrame {
+                user_id,
+                username,
+                frame: PixelFrame {
+                    width,
+                    height,
+                    data,
+                    format,
+                },
+            })
+        }
+        kind => Err(anyhow!("Unknown message kind: {}", kind)),
+    }
+}
+
+/// Wrap a message into a WebSocket message for the given encoding
+///
+/// Compression only applies to the binary encoding.
+pub fn to_ws(msg: &Message, encoding: Encoding, compression: Compression) -> Result<WsMessage> {
+    match encoding {
+        Encoding::Json => Ok(WsMessage::Text(serde_json::to_string(msg)?)),
+        Encoding::Binary => Ok(WsMessage::Binary(encode(msg, compression)?)),
+    }
+}
+
+/// Parse a text or binary WebSocket message, whichever encoding the peer used
+pub fn from_ws(ws_msg: &WsMessage) -> Result<Message> {
+    match ws_msg {
+        WsMessage::Text(text) => Ok(serde_json::from_str(text)?),
+        WsMessage::Binary(bytes) => decode(bytes),
+        other => Err(anyhow!("Not a data message: {:?}", other)),
+    }
+}
+
+/// Append a u16 length-prefixed UTF-8 string
+fn put_str(out: &mut Vec<u8>, s: &str) -> Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| anyhow!("String too long for wire: {}", s.len()))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Bounds-checked cursor over a binary payload
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos + n;
+        if end > self.bytes.len() {
+            bail!("Truncated message");
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let slice = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        slice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut frame = AsciiFrame::new(3, 2);
+        frame.set_cell(1, 1, '@', 10, 20, 30);
+        let msg = Message::Frame {
+            user_id: "id".to_string(),
+            username: "alice".to_string(),
+            frame,
+            seq: 7,
+        };
+
+        let bytes = encode(&msg, Compression::None).unwrap();
+        // Header + flags + seq + dimensions + two strings + 6 cells
+        assert_eq!(bytes.len(), 4 + 1 + 4 + 4 + (2 + 2) + (2 + 5) + 24);
+
+        match decode(&bytes).unwrap() {
+            Message::Frame { user_id, username, frame, seq } => {
+                assert_eq!(user_id, "id");
+                assert_eq!(username, "alice");
+                assert_eq!(seq, 7);
+                assert_eq!(frame.get_cell(1, 1), Some(('@', 10, 20, 30)));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compressed_frame_roundtrip() {
+        let mut frame = AsciiFrame::new(40, 10);
+        frame.set_cell(5, 5, '#', 255, 0, 0);
+        let msg = Message::Frame {
+            user_id: "id".to_string(),
+            username: "alice".to_string(),
+            frame: frame.clone(),
+            seq: 1,
+        };
+
+        let plain = encode(&msg, Compression::None).unwrap();
+        let packed = encode(&msg, Compression::Deflate).unwrap();
+        assert!(packed.len() * 4 < plain.len());
+
+        match decode(&packed).unwrap() {
+            Message::Frame { frame: decoded, .. } => assert_eq!(decoded.data, frame.data),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let a = AsciiFrame::new(4, 2);
+        let mut b = a.clone();
+        b.set_cell(2, 1, '#', 1, 2, 3);
+        let msg = Message::FrameDelta {
+            user_id: "id".to_string(),
+            username: "alice".to_string(),
+            delta: FrameDelta::between(&a, &b, 3, 4).unwrap(),
+        };
+
+        for compression in [Compression::None, Compression::Rle, Compression::Deflate] {
+            match decode(&encode(&msg, compression).unwrap()).unwrap() {
+                Message::FrameDelta { delta, .. } => {
+                    assert_eq!((delta.seq, delta.base_seq), (4, 3));
+                    let mut rebuilt = a.clone();
+                    delta.apply(&mut rebuilt).unwrap();
+                    assert_eq!(rebuilt.data, b.data);
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unicode_frame_roundtrip() {
+        let mut frame = AsciiFrame::new(5, 1);
+        frame.set_cell(0, 0, '\u{2593}', 1, 2, 3);
+        frame.set_cell(4, 0, '\u{2847}', 4, 5, 6);
+        let msg = Message::Frame {
+            user_id: String::new(),
+            username: String::new(),
+            frame,
+            seq: 0,
+        };
+
+        match decode(&encode(&msg, Compression::Rle).unwrap()).unwrap() {
+            Message::Frame { frame, .. } => {
+                assert!(frame.format.unicode);
+                assert_eq!(frame.get_cell(0, 0), Some(('\u{2593}', 1, 2, 3)));
+                assert_eq!(frame.get_cell(4, 0), Some(('\u{2847}', 4, 5, 6)));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pixel_frame_roundtrip() {
+        for format in [PixelFormat::Rgb, PixelFormat::Luma] {
+            let frame = PixelFrame {
+                width: 4,
+                height: 2,
+                data: (0..8 * format.bytes_per_pixel() as u8).collect(),
+                format,
+            };
+            let msg = Message::PixelFrame {
+                user_id: "id".to_string(),
+                username: "alice".to_string(),
+                frame: frame.clone(),
+            };
+
+            for compression in [Compression::None, Compression::Rle, Compression::Deflate] {
+                match decode(&encode(&msg, compression).unwrap()).unwrap() {
+                    Message::PixelFrame { username, frame: decoded, .. } => {
+                        assert_eq!(username, "alice");
+                        assert_eq!(decoded, frame);
+                    }
+                    other => panic!("unexpected message: {:?}", other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_body_roundtrip() {
+        let msg = Message::Chat {
+            user_id: "id".to_string(),
+            username: "bob".to_string(),
+            content: "hi".to_string(),
+        };
+        match decode(&encode(&msg, Compression::None).unwrap()).unwrap() {
+            Message::Chat { content, .. } => assert_eq!(content, "hi"),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        assert!(decode(b"").is_err());
+        assert!(decode(&[MAGIC, WIRE_VERSION + 1, KIND_JSON, 0]).is_err());
+        assert!(decode(&[b'X', WIRE_VERSION, KIND_JSON, 0]).is_err());
+        assert!(decode(&[MAGIC, WIRE_VERSION, KIND_FRAME, 9]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_short_frame() {
+        let msg = Message::Frame {
+            user_id: String::new(),
+            username: String::new(),
+            frame: AsciiFrame::new(2, 2),
+            seq: 0,
+        };
+        let mut bytes = encode(&msg, Compression::None).unwrap();
+        bytes.pop();
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_legacy_join_defaults_to_json() {
+        let msg: Message = serde_json::from_str(r#"{"type":"Join","data":{"username":"old"}}"#).unwrap();
+        match msg {
+            Message::Join {
+                encoding, cell_format, ..
+            } => {
+                assert_eq!(encoding, Encoding::Json);
+                assert_eq!(cell_format, CellFormat::default());
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+}